@@ -0,0 +1,88 @@
+//! Conserved quantities of the 3D simulation and their drift over time.
+
+use std::fmt;
+
+
+/// Particle as kept in `main`: (position, velocity, acceleration).
+pub type Particle = ((f32, f32, f32), (f32, f32, f32), (f32, f32, f32));
+
+
+/// Snapshot of conserved quantities, computed on demand (potential is O(n^2)).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Diagnostics {
+    pub n:        usize,
+    pub kinetic:  f64,             // sum of m*v^2/2
+    pub gravity:  f64,             // gravitational potential energy
+    pub momentum: (f64, f64, f64), // sum of m*v
+    pub angular:  (f64, f64, f64), // sum of m*(x cross v), around the origin
+}
+
+impl Diagnostics {
+    /// All particles have mass `m`; gravity is `g*m*m/d^2` with `d` clamped to `d_thr`
+    /// (the potential uses the same clamp, so it is only approximate inside `d_thr`).
+    pub fn measure(particles: &[Particle], m: f32, g: f32, d_thr: f32) -> Diagnostics {
+        let m = m as f64;
+        let mut d = Diagnostics { n: particles.len(), kinetic: 0.0, gravity: 0.0,
+                                  momentum: (0.0, 0.0, 0.0), angular: (0.0, 0.0, 0.0) };
+
+        for (i, p) in particles.iter().enumerate() {
+            let x = ((p.0).0 as f64, (p.0).1 as f64, (p.0).2 as f64);
+            let v = ((p.1).0 as f64, (p.1).1 as f64, (p.1).2 as f64);
+
+            d.kinetic    += 0.5*m*(v.0*v.0 + v.1*v.1 + v.2*v.2);
+            d.momentum.0 += m*v.0;
+            d.momentum.1 += m*v.1;
+            d.momentum.2 += m*v.2;
+            d.angular.0  += m*(x.1*v.2 - x.2*v.1);
+            d.angular.1  += m*(x.2*v.0 - x.0*v.2);
+            d.angular.2  += m*(x.0*v.1 - x.1*v.0);
+
+            // every pair once
+            for o in &particles[i + 1..] {
+                let dnm  = ((o.0).0 - (p.0).0, (o.0).1 - (p.0).1, (o.0).2 - (p.0).2);
+                let dist = (dnm.0*dnm.0 + dnm.1*dnm.1 + dnm.2*dnm.2).sqrt().max(d_thr);
+                d.gravity -= (g as f64)*m*m/(dist as f64);
+            }
+        }
+
+        d
+    }
+
+    pub fn total_energy(&self) -> f64 { self.kinetic + self.gravity }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "n={} E={:.6e} (K={:.6e} Ug={:.6e}) P=({:.3e}, {:.3e}, {:.3e}) L=({:.3e}, {:.3e}, {:.3e})",
+               self.n, self.total_energy(), self.kinetic, self.gravity,
+               self.momentum.0, self.momentum.1, self.momentum.2,
+               self.angular.0, self.angular.1, self.angular.2)
+    }
+}
+
+
+/// Records measurements over a run, drift is reported against the first one.
+#[derive(Clone, Debug, Default)]
+pub struct DriftLog {
+    history: Vec<(u64, Diagnostics)>,
+}
+
+impl DriftLog {
+    pub fn new() -> DriftLog { DriftLog::default() }
+
+    /// Stores `d` measured at `frame`, returns (relative energy drift, |momentum drift|, |angular momentum drift|).
+    pub fn record(&mut self, frame: u64, d: Diagnostics) -> (f64, f64, f64) {
+        self.history.push((frame, d));
+        let r = self.history[0].1;
+
+        let de = d.total_energy() - r.total_energy();
+        let de = if r.total_energy() != 0.0 { de/r.total_energy().abs() } else { de };
+        let len = |v: (f64, f64, f64)| (v.0*v.0 + v.1*v.1 + v.2*v.2).sqrt();
+        let dp  = len((d.momentum.0 - r.momentum.0, d.momentum.1 - r.momentum.1, d.momentum.2 - r.momentum.2));
+        let dl  = len((d.angular.0 - r.angular.0, d.angular.1 - r.angular.1, d.angular.2 - r.angular.2));
+
+        (de, dp, dl)
+    }
+
+    pub fn history(&self) -> &[(u64, Diagnostics)] { &self.history }
+}
@@ -4,14 +4,31 @@ extern crate rand;
 
 use glium::Surface;
 use glium::glutin;
+use rand::{Rng, SeedableRng, XorShiftRng};
 
 mod support;
+mod diagnostics;
+mod snapshot;
+mod timestep;
+
+/// Random generator for the initial state; the same seed gives the same teapots.
+fn seeded_rng(seed: u64) -> XorShiftRng {
+    let (lo, hi) = (seed as u32, (seed >> 32) as u32);
+    // mixed with the default xorshift state, never all zero
+    XorShiftRng::from_seed([lo ^ 0x193a_6754, hi ^ 0xa8a7_d469, lo ^ 0x9783_0e05, hi ^ 0x113b_a7bb])
+}
+
 
 fn main() {
     const GLSL_COMPUTE: bool = true;
     const NUM_VALUES: usize = 16384;
     const NUM_GROUPS: usize = 128;
-    const DT: f32 = 0.005;
+    const DT: f32 = 0.005;           // fixed step, or the largest adaptive one
+    const ADAPTIVE_DT: Option<timestep::AdaptiveDt> = Some(timestep::AdaptiveDt {
+        eta: 0.2, courant: 0.5, length: 0.05, min_dt: DT/50.0, max_dt: DT });
+    const G: f32 = if GLSL_COMPUTE { 0.0002 } else { 0.0001 }; // gravity of the update path in use (the shader has its own copy)
+    const D_THR: f32 = 0.01;         // force clamp distance, on both paths
+    const DIAG_EVERY: u64 = 100;     // frames between measurements
 
     use glium::DisplayBuild;
 
@@ -32,17 +49,31 @@ fn main() {
     let vertex_buffer = support::load_wavefront(&display, include_bytes!("models/icosphere.obj"));
     let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
 
+    // `--seed <n>` repeats the initial state of a run, `--resume <file>` continues a run saved with F5
+    let (mut seed, mut resume) = (None, None);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed"   => seed   = Some(args.next().and_then(|s| s.parse().ok()).expect("--seed needs a number")),
+            "--resume" => resume = Some(args.next().expect("--resume needs a snapshot file")),
+            _ => (),
+        }
+    }
+    let seed: u64 = seed.unwrap_or_else(rand::random);
+    println!("seed: {}", seed);
+    let mut rng = seeded_rng(seed);
+
     // list of teapots with position and direction
     let mut teapots = (0 .. NUM_VALUES)
         .map(|i| {
             let dir = {if i < NUM_VALUES/2 as usize {-1.0} else {1.0}};
             
-            let pos: (f32, f32, f32) = (rand::random(), rand::random(), rand::random());
+            let pos: (f32, f32, f32) = (rng.gen(), rng.gen(), rng.gen());
             let pos = ( pos.0*0.1 + dir*0.5, 
                         pos.1*0.1 + dir*0.2, 
                         pos.2*0.1 + dir*0.4);
             
-            let vel: (f32, f32, f32) = (rand::random(), rand::random(), rand::random());
+            let vel: (f32, f32, f32) = (rng.gen(), rng.gen(), rng.gen());
             let vel = ( (vel.0 * 1.5 - 0.75)*4.0 + dir*0.1, 
                         (vel.1 * 1.5 - 0.75)*4.0 - dir*0.3, 
                         (vel.2 * 1.5 - 0.75)*4.0 );
@@ -53,6 +84,21 @@ fn main() {
         })
         .collect::<Vec<_>>();
 
+    // frame counter and time step, both replaced when resuming a snapshot
+    let mut nframes: u64 = 0;
+    let mut dt = DT;
+    let params = snapshot::Params { gpu: GLSL_COMPUTE, g: G, dthr: D_THR, n: NUM_VALUES };
+
+    if let Some(path) = resume {
+        let snap = snapshot::Snapshot::load(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        snap.check(&params).unwrap_or_else(|e| panic!("{}: {}", path, e));
+
+        println!("resumed {} at frame {}", path, snap.frame);
+        teapots = snap.particles;
+        nframes = snap.frame;
+        dt      = snap.dt;
+    }
+
     // building the vertex buffer with the attributes per instance
     let mut per_instance = {
         #[derive(Copy, Clone)]
@@ -271,8 +317,20 @@ fn main() {
 
     let mut camera = support::camera::CameraState::new();
     
+    // conserved quantities log, toggled with F2 (measuring is O(n^2) on the CPU, so off by default)
+    let mut diagnostics_on = false;
+    let mut diag_log = diagnostics::DriftLog::new();
+    
     // the main loop
     support::start_loop(|| {
+        // shrinking the step when particles come close or move fast, reported in the title
+        if let Some(adaptive) = ADAPTIVE_DT {
+            dt = adaptive.dt(&teapots);
+        }
+        if let Some(win) = display.get_window() {
+            win.set_title(&format!("frame {}  dt {:.2e}{}", nframes, dt, if ADAPTIVE_DT.is_some() { " (adaptive)" } else { "" }));
+        }
+
     
         if GLSL_COMPUTE { // update using shader
             { // filling buffer with points vector, masses vector
@@ -317,14 +375,14 @@ fn main() {
                     (teapot.2).2 = force3d.2/mass;
 
                     // v = v + a*t
-                    (teapot.1).0 += (teapot.2).0*DT;
-                    (teapot.1).1 += (teapot.2).1*DT;
-                    (teapot.1).2 += (teapot.2).2*DT;
+                    (teapot.1).0 += (teapot.2).0*dt;
+                    (teapot.1).1 += (teapot.2).1*dt;
+                    (teapot.1).2 += (teapot.2).2*dt;
                 
                     // x = x + v*t
-                    (teapot.0).0 += (teapot.1).0*DT;
-                    (teapot.0).1 += (teapot.1).1*DT;
-                    (teapot.0).2 += (teapot.1).2*DT;
+                    (teapot.0).0 += (teapot.1).0*dt;
+                    (teapot.0).1 += (teapot.1).1*dt;
+                    (teapot.0).2 += (teapot.1).2*dt;
                     
                     //println!("{:?} -> {:?} -> {:?} -> {:?}", pos3d, mass, force3d, teapot.2);
                     //println!("{:?} -> {:?}", i,  mapcsbuf.values_mid[i]);
@@ -350,14 +408,14 @@ fn main() {
                     
                     if ox.0 == tx.0 && ox.1 == tx.1 && ox.2 == tx.2 { continue; }
                     
-                    let d_thr = 0.01;                
+                    let d_thr = D_THR;
             
                     let dnm   = (ox.0 - tx.0, ox.1 - tx.1, ox.2 - tx.2);                  // distance vector
                     let mut d = (dnm.0*dnm.0 + dnm.1*dnm.1 + dnm.2*dnm.2).sqrt(); // distance scalar
                     if d < d_thr {d = d_thr;}
                     let dirv  = (dnm.0/d, dnm.1/d, dnm.2/d);                          // direction vector
 
-                    let fg = 0.0001*tm*om/(d*d);     // gravity force scalar
+                    let fg = G*tm*om/(d*d);     // gravity force scalar
                     let fgnm = (fg*dirv.0, fg*dirv.1, fg*dirv.2); // gravity force vector
                     
                     fv.0 += fgnm.0;
@@ -371,20 +429,28 @@ fn main() {
                 (src.2).2 = fv.2/tm;
 
                 // v = v + a*t
-                (src.1).0 += (src.2).0*DT;
-                (src.1).1 += (src.2).1*DT;
-                (src.1).2 += (src.2).2*DT;
+                (src.1).0 += (src.2).0*dt;
+                (src.1).1 += (src.2).1*dt;
+                (src.1).2 += (src.2).2*dt;
             
                 // x = x + v*t
-                (src.0).0 += (src.1).0*DT;
-                (src.0).1 += (src.1).1*DT;
-                (src.0).2 += (src.1).2*DT;
+                (src.0).0 += (src.1).0*dt;
+                (src.0).1 += (src.1).1*dt;
+                (src.0).2 += (src.1).2*dt;
 
                 dest.world_position = src.0;
             }
             // end of updating using CPU
         } 
         
+        if diagnostics_on && nframes % DIAG_EVERY == 0 {
+            // same gravity constant and threshold as the update path in use
+            let d = diagnostics::Diagnostics::measure(&teapots, 1.0, G, D_THR);
+            let (de, dp, dl) = diag_log.record(nframes, d);
+            println!("frame {}: {} | dE/E0={:+.3e} |dP|={:.3e} |dL|={:.3e}", nframes, d, de, dp, dl);
+        }
+        nframes += 1;
+        
         camera.update();
 
         // building the uniforms
@@ -413,6 +479,20 @@ fn main() {
         for event in display.poll_events() {
             match event {
                 glutin::Event::Closed => return support::Action::Stop,
+                glutin::Event::KeyboardInput(glutin::ElementState::Pressed, _, Some(glutin::VirtualKeyCode::F5)) => {
+                    let path = format!("snapshot_{:08}.txt", nframes);
+                    let snap = snapshot::Snapshot { frame: nframes, dt, params, particles: teapots.clone() };
+                    match snap.save(&path) {
+                        Ok(())   => println!("saved {}", path),
+                        Err(err) => println!("{}: {}", path, err),
+                    }
+                },
+                glutin::Event::KeyboardInput(glutin::ElementState::Pressed, _, Some(glutin::VirtualKeyCode::F2)) => {
+                    // drift is measured from the first frame after switching on
+                    diagnostics_on = !diagnostics_on;
+                    diag_log = diagnostics::DriftLog::new();
+                    println!("diagnostics: {}", if diagnostics_on { "on" } else { "off" });
+                },
                 ev => camera.process_input(&ev),
             }
         }
@@ -0,0 +1,124 @@
+//! Saving and resuming the 3D simulation as a versioned text file.
+//!
+//! ```text
+//! nbody-snapshot 2
+//! frame 1500
+//! dt 0.005
+//! update gpu                    (or cpu)
+//! g 0.0002
+//! dthr 0.01
+//! n 16384
+//! px py pz vx vy vz ax ay az    (one line per particle)
+//! ```
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use diagnostics::Particle;
+
+
+/// Format version written to new snapshots; other versions are rejected.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+const MAGIC: &'static str = "nbody-snapshot";
+
+
+/// Simulation parameters a snapshot was saved with; a run resumes only with the same ones.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Params {
+    pub gpu:  bool,  // compute shader update path, or the CPU one
+    pub g:    f32,   // gravity constant of that path
+    pub dthr: f32,   // force clamp distance
+    pub n:    usize, // particle count (`NUM_VALUES`)
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub frame:     u64,
+    pub dt:        f32,
+    pub params:    Params,
+    pub particles: Vec<Particle>,
+}
+
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// reads `<key> <value>` header line
+fn header<R: BufRead>(lines: &mut io::Lines<R>, key: &str) -> io::Result<String> {
+    let line = lines.next().unwrap_or_else(|| Err(invalid(format!("missing '{}'", key))))?;
+    let mut it = line.split_whitespace();
+    match (it.next(), it.next()) {
+        (Some(k), Some(v)) if k == key => Ok(v.to_string()),
+        _ => Err(invalid(format!("expected '{} <value>', got '{}'", key, line))),
+    }
+}
+
+
+impl Snapshot {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut f = BufWriter::new(File::create(path)?);
+        writeln!(f, "{} {}", MAGIC, SNAPSHOT_VERSION)?;
+        writeln!(f, "frame {}", self.frame)?;
+        writeln!(f, "dt {}", self.dt)?;
+        writeln!(f, "update {}", if self.params.gpu { "gpu" } else { "cpu" })?;
+        writeln!(f, "g {}", self.params.g)?;
+        writeln!(f, "dthr {}", self.params.dthr)?;
+        writeln!(f, "n {}", self.particles.len())?;
+
+        for p in &self.particles {
+            writeln!(f, "{} {} {} {} {} {} {} {} {}",
+                     (p.0).0, (p.0).1, (p.0).2,
+                     (p.1).0, (p.1).1, (p.1).2,
+                     (p.2).0, (p.2).1, (p.2).2)?;
+        }
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+
+        let version = header(&mut lines, MAGIC)?;
+        if version != SNAPSHOT_VERSION.to_string() {
+            return Err(invalid(format!("unsupported snapshot version {} (expected {})", version, SNAPSHOT_VERSION)));
+        }
+
+        let parse_err = |e: ::std::num::ParseIntError| invalid(e.to_string());
+        let float_err = |e: ::std::num::ParseFloatError| invalid(e.to_string());
+        let frame: u64   = header(&mut lines, "frame")?.parse().map_err(parse_err)?;
+        let dt:    f32   = header(&mut lines, "dt")?.parse().map_err(float_err)?;
+        let gpu = match header(&mut lines, "update")?.as_str() {
+            "gpu" => true,
+            "cpu" => false,
+            other => return Err(invalid(format!("unknown update path '{}'", other))),
+        };
+        let g:     f32   = header(&mut lines, "g")?.parse().map_err(float_err)?;
+        let dthr:  f32   = header(&mut lines, "dthr")?.parse().map_err(float_err)?;
+        let n:     usize = header(&mut lines, "n")?.parse().map_err(parse_err)?;
+
+        let mut particles = Vec::with_capacity(n);
+        for line in lines.take(n) {
+            let line = line?;
+            let v = line.split_whitespace().map(|s| s.parse::<f32>()).collect::<Result<Vec<_>, _>>()
+                .map_err(|e| invalid(e.to_string()))?;
+            if v.len() != 9 { return Err(invalid(format!("expected 9 values per particle, got '{}'", line))); }
+
+            particles.push(((v[0], v[1], v[2]), (v[3], v[4], v[5]), (v[6], v[7], v[8])));
+        }
+        if particles.len() != n {
+            return Err(invalid(format!("expected {} particles, got {}", n, particles.len())));
+        }
+
+        Ok(Snapshot { frame, dt, params: Params { gpu, g, dthr, n }, particles })
+    }
+
+    /// Checks that the snapshot was saved with `params`, the ones of this build.
+    pub fn check(&self, params: &Params) -> Result<(), String> {
+        if self.params == *params { return Ok(()); }
+        Err(format!("saved with {:?}, this build runs {:?}", self.params, params))
+    }
+}
@@ -0,0 +1,28 @@
+//! Adaptive global timestep of the 3D simulation, for close encounters.
+
+use diagnostics::Particle;
+
+
+/// Criteria and bounds of the step; `length` is the scale that must be resolved.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdaptiveDt {
+    pub eta:     f32, // acceleration criterion: eta*sqrt(length/|a|)
+    pub courant: f32, // Courant criterion: courant*length/|v|
+    pub length:  f32,
+    pub min_dt:  f32,
+    pub max_dt:  f32,
+}
+
+impl AdaptiveDt {
+    /// Step for the current particles (accelerations from the last frame),
+    /// the smaller of both criteria within `[min_dt, max_dt]`.
+    pub fn dt(&self, particles: &[Particle]) -> f32 {
+        let sq = |v: (f32, f32, f32)| v.0*v.0 + v.1*v.1 + v.2*v.2;
+        let (a2, v2) = particles.iter().fold((0.0f32, 0.0f32), |(a2, v2), p| (a2.max(sq(p.2)), v2.max(sq(p.1))));
+
+        let mut dt = self.max_dt;
+        if a2 > 0.0 { dt = dt.min(self.eta*(self.length/a2.sqrt()).sqrt()); }
+        if v2 > 0.0 { dt = dt.min(self.courant*self.length/v2.sqrt()); }
+        dt.max(self.min_dt).min(self.max_dt)
+    }
+}
@@ -0,0 +1,112 @@
+//! Boundary conditions of the 2D world.
+//!
+//! The box is centered at the origin; `w` and `h` are its full width and height.
+
+use soa::Particles;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Boundary {
+    #[default]
+    Open,                          // no walls, particles may fly off
+    Reflective { w: f32, h: f32 }, // particles bounce off the walls
+    Periodic   { w: f32, h: f32 }, // particles wrap around, forces use minimum image
+}
+
+impl Boundary {
+    /// Box size, if there is one.
+    pub fn size(&self) -> Option<(f32, f32)> {
+        match *self {
+            Boundary::Open => None,
+            Boundary::Reflective { w, h } | Boundary::Periodic { w, h } => Some((w, h)),
+        }
+    }
+
+    /// Position of the image of `to` closest to `from` (minimum image convention).
+    pub fn image(&self, from: (f32, f32), to: (f32, f32)) -> (f32, f32) {
+        match *self {
+            Boundary::Periodic { w, h } => {
+                let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+                (from.0 + dx - w*(dx/w).round(), from.1 + dy - h*(dy/h).round())
+            },
+            _ => to,
+        }
+    }
+
+    /// Moves nodes that left the box back in: mirrored for reflective walls,
+    /// wrapped for periodic ones. Returns the number of nodes mirrored; wrapping
+    /// leaves the minimum-image forces unchanged, so it is not counted.
+    pub fn apply(&self, v: &mut Particles) -> usize {
+        match *self {
+            Boundary::Open => 0,
+            Boundary::Reflective { w, h } => {
+                let mut reflected = 0;
+                for i in 0..v.len() {
+                    let rx = reflect(&mut v.px[i], &mut v.vx[i], 0.5*w);
+                    let ry = reflect(&mut v.py[i], &mut v.vy[i], 0.5*h);
+                    if rx || ry { reflected += 1; }
+                }
+                reflected
+            },
+            Boundary::Periodic { w, h } => {
+                for x in &mut v.px { *x -= w*(*x/w).round(); }
+                for y in &mut v.py { *y -= h*(*y/h).round(); }
+                0
+            },
+        }
+    }
+}
+
+
+// mirrors a coordinate outside [-half, half] back in, turning its velocity inwards;
+// true if it was outside
+fn reflect(x: &mut f32, v: &mut f32, half: f32) -> bool {
+    let outside = x.abs() > half;
+    if *x < -half { *x = -2.0*half - *x; *v =  v.abs(); }
+    if *x >  half { *x =  2.0*half - *x; *v = -v.abs(); }
+
+    // very fast nodes could still be outside after one mirror
+    *x = x.max(-half).min(half);
+    outside
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use node::Node;
+    use world::World;
+
+    #[test]
+    fn reflective_walls_mirror_position_and_velocity() {
+        let mut v = Particles::from_nodes(&[Node::new(105.0, -52.0, 3.0, -2.0, 1.0, 0.0),
+                                            Node::new(  0.0,   0.0, 3.0, -2.0, 1.0, 0.0)]);
+        assert_eq!(Boundary::Reflective { w: 200.0, h: 100.0 }.apply(&mut v), 1);
+
+        assert_eq!((v.px[0], v.py[0]), (95.0, -48.0));
+        assert_eq!((v.vx[0], v.vy[0]), (-3.0, 2.0));
+    }
+
+    #[test]
+    fn periodic_wraps_and_uses_minimum_image() {
+        let b = Boundary::Periodic { w: 200.0, h: 100.0 };
+        let mut v = Particles::from_nodes(&[Node::new(105.0, -52.0, 3.0, -2.0, 1.0, 0.0)]);
+        b.apply(&mut v);
+
+        assert_eq!((v.px[0], v.py[0]), (-95.0, 48.0));
+        assert_eq!(b.image((-95.0, 0.0), (95.0, 0.0)), (-105.0, 0.0));
+        assert_eq!(Boundary::Open.image((-95.0, 0.0), (95.0, 0.0)), (95.0, 0.0));
+    }
+
+    #[test]
+    fn periodic_forces_act_across_the_wrap() {
+        let mut w = World::new();
+        w.set_boundary(Boundary::Periodic { w: 200.0, h: 100.0 });
+        w.add_node(Node::new(-95.0, 0.0, 0.0, 0.0, 10.0, 0.0));
+        w.add_node(Node::new( 95.0, 0.0, 0.0, 0.0, 10.0, 0.0));
+        w.step(0.01);
+
+        assert!(w.node(0).fx < 0.0 && w.node(1).fx > 0.0);
+    }
+}
@@ -0,0 +1,183 @@
+//! Cell list (uniform-grid spatial hash) for short-range forces in O(n).
+//!
+//! Space is cut into square cells no smaller than the cutoff radius, so all
+//! neighbours of a node lie in its own cell or the 8 around it. Cells are
+//! hashed into a table of buckets sized to the node count, so the grid needs
+//! no bounds and costs nothing for empty space. With periodic boundary the
+//! cells tile the box exactly and wrap around.
+
+use node::Node;
+use force::{ForceLaw, pair_force};
+use boundary::Boundary;
+
+
+/// Grid built over a snapshot of nodes; indices refer to that snapshot.
+/// Rebuilding reuses the buffers, so a grid kept across steps does not allocate.
+#[derive(Clone, Debug, Default)]
+pub struct CellGrid {
+    cutoff: f32,
+    cell:   (f32, f32),          // cell size, at least `cutoff`
+    wrap:   Option<(i64, i64)>,  // number of cells across a periodic box
+    mask:   usize,               // bucket count - 1, a power of two
+    starts: Vec<usize>,          // range of every bucket in `order`
+    order:  Vec<usize>,          // node indices, grouped by bucket
+    keys:   Vec<usize>,          // bucket of every node, scratch
+    fill:   Vec<usize>,          // next free slot of every bucket, scratch
+}
+
+impl CellGrid {
+    pub fn build(nodes: &[Node], cutoff: f32, boundary: Boundary) -> CellGrid {
+        let mut grid = CellGrid::default();
+        grid.rebuild(nodes, cutoff, boundary);
+        grid
+    }
+
+    pub fn cutoff(&self) -> f32 { self.cutoff }
+
+    pub fn len(&self) -> usize { self.order.len() }
+
+    pub fn is_empty(&self) -> bool { self.order.is_empty() }
+
+    /// Sorts `nodes` into buckets again (counting sort, O(n)).
+    pub fn rebuild(&mut self, nodes: &[Node], cutoff: f32, boundary: Boundary) {
+        self.cutoff = cutoff;
+        match boundary {
+            Boundary::Periodic { w, h } => {
+                let (nx, ny) = ((w/cutoff).floor().max(1.0), (h/cutoff).floor().max(1.0));
+                self.cell = (w/nx, h/ny);
+                self.wrap = Some((nx as i64, ny as i64));
+            },
+            _ => {
+                self.cell = (cutoff, cutoff);
+                self.wrap = None;
+            },
+        }
+
+        let buckets = (2*nodes.len()).next_power_of_two().max(16);
+        self.mask = buckets - 1;
+
+        let mut keys = ::std::mem::take(&mut self.keys);
+        keys.clear();
+        keys.extend(nodes.iter().map(|n| {
+            let (ix, iy) = self.cell_of(n.px, n.py, boundary);
+            self.bucket(ix, iy)
+        }));
+        self.keys = keys;
+
+        // counting sort: bucket sizes, prefix sums, then scattering
+        self.starts.clear();
+        self.starts.resize(buckets + 1, 0);
+        for &k in &self.keys { self.starts[k + 1] += 1; }
+        for b in 0..buckets { self.starts[b + 1] += self.starts[b]; }
+
+        self.order.clear();
+        self.order.resize(nodes.len(), 0);
+        self.fill.clear();
+        self.fill.extend_from_slice(&self.starts[..buckets]);
+        for (i, &k) in self.keys.iter().enumerate() {
+            self.order[self.fill[k]] = i;
+            self.fill[k] += 1;
+        }
+    }
+
+    // integer cell coordinates, wrapped into the box when periodic
+    fn cell_of(&self, x: f32, y: f32, boundary: Boundary) -> (i64, i64) {
+        let (ox, oy) = boundary.size().map_or((0.0, 0.0), |(w, h)| (0.5*w, 0.5*h));
+        let (ix, iy) = (((x + ox)/self.cell.0).floor() as i64, ((y + oy)/self.cell.1).floor() as i64);
+        self.wrapped(ix, iy)
+    }
+
+    fn wrapped(&self, ix: i64, iy: i64) -> (i64, i64) {
+        match self.wrap {
+            Some((nx, ny)) => (ix.rem_euclid(nx), iy.rem_euclid(ny)),
+            None => (ix, iy),
+        }
+    }
+
+    fn bucket(&self, ix: i64, iy: i64) -> usize {
+        (ix.wrapping_mul(73_856_093) ^ iy.wrapping_mul(19_349_663)) as usize & self.mask
+    }
+
+    /// Total force on `n` from nodes within the cutoff; `nodes` must be the snapshot
+    /// the grid was built from. Pairs further apart are ignored, whatever the laws.
+    pub fn force_on<L: ForceLaw + ?Sized>(&self, n: &Node, nodes: &[Node], boundary: Boundary,
+                                          laws: &L) -> (f32, f32) {
+        let from = (n.px, n.py);
+        let mut fv = (0.0, 0.0);
+        if self.order.is_empty() { return fv; }
+
+        // buckets of the 3x3 neighbourhood, each once (small boxes and hash collisions repeat them)
+        let (cx, cy) = self.cell_of(n.px, n.py, boundary);
+        let mut seen = [usize::MAX; 9];
+        for (k, (dx, dy)) in (-1..2).flat_map(|dy| (-1..2).map(move |dx| (dx, dy))).enumerate() {
+            let (ix, iy) = self.wrapped(cx + dx, cy + dy);
+            let b = self.bucket(ix, iy);
+            if seen[..k].contains(&b) { continue; }
+            seen[k] = b;
+
+            for &i in &self.order[self.starts[b]..self.starts[b + 1]] {
+                let m = &nodes[i];
+                if n.px == m.px && n.py == m.py { continue; }
+
+                let (px, py) = boundary.image(from, (m.px, m.py));
+                let (dx, dy) = (px - n.px, py - n.py);
+                if dx*dx + dy*dy > self.cutoff*self.cutoff { continue; }
+
+                let f = pair_force(n, &Node { px, py, ..*m }, laws);
+                fv.0 += f.0; fv.1 += f.1;
+            }
+        }
+
+        fv
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use node::init_nodes_vec;
+    use soa::Particles;
+    use world::{node_force, seeded_rng};
+    use force::{Law, LennardJones, Yukawa};
+
+    #[test]
+    fn matches_exact_sum_for_short_range_laws() {
+        let mut v = Vec::new();
+        init_nodes_vec(&mut v, 400, &mut seeded_rng(3));
+        let laws = [Law::LennardJones(LennardJones { epsilon: 1.0, sigma: 1.0, cutoff: 3.0 })];
+
+        for &boundary in &[Boundary::Open, Boundary::Periodic { w: 40.0, h: 500.0 }] {
+            let mut p = Particles::from_nodes(&v);
+            boundary.apply(&mut p);
+            v = p.to_nodes();
+            let grid = CellGrid::build(&v, 3.0, boundary);
+            assert_eq!(grid.len(), v.len());
+
+            for n in v.iter().step_by(7) {
+                let (e, a) = (node_force(n, &v, boundary, &laws[..]), grid.force_on(n, &v, boundary, &laws[..]));
+                assert!((e.0 - a.0).abs() <= 1e-3*e.0.abs().max(1.0), "{:?} {:?} {:?}", boundary, e, a);
+                assert!((e.1 - a.1).abs() <= 1e-3*e.1.abs().max(1.0), "{:?} {:?} {:?}", boundary, e, a);
+            }
+        }
+    }
+
+    #[test]
+    fn ignores_pairs_beyond_cutoff_and_wraps_small_boxes() {
+        let v = vec![Node::new(0.0, 0.0, 0.0, 0.0, 1.0, 1.0),
+                     Node::new(4.0, 0.0, 0.0, 0.0, 1.0, 1.0),
+                     Node::new(9.0, 0.0, 0.0, 0.0, 1.0, 1.0)];
+        let laws = [Yukawa { k: 1.0, lambda: 2.0, dthr: 1.0, cutoff: Some(5.0) }];
+
+        let grid = CellGrid::build(&v, 5.0, Boundary::Open);
+        let f = grid.force_on(&v[0], &v, Boundary::Open, &laws[..]);
+        assert_eq!(f, node_force(&v[0], &v[1..2], Boundary::Open, &laws[..]));
+
+        // a box of two cells: the far node is a near image, counted once
+        let b = Boundary::Periodic { w: 12.0, h: 12.0 };
+        let grid = CellGrid::build(&v, 5.0, b);
+        let f = grid.force_on(&v[0], &v, b, &laws[..]);
+        let e = node_force(&v[0], &v, b, &laws[..]);
+        assert!((f.0 - e.0).abs() <= 1e-6 && f.1 == e.1, "{:?} {:?}", f, e);
+    }
+}
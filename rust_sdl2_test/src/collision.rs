@@ -0,0 +1,180 @@
+//! Contact handling between overlapping nodes.
+//!
+//! Nodes are spheres of radius `diameter(m)/2`. Overlaps are found with
+//! a sweep along x, so only neighbours in x are compared.
+
+use node::{Node, diameter};
+use soa::Particles;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub enum Collisions {
+    #[default]
+    Off,      // particles pass through each other (only the force clamp applies)
+    Merge,    // perfectly inelastic: overlapping nodes become one
+    Elastic,  // overlapping nodes bounce off each other
+}
+
+impl Collisions {
+    /// Resolves all overlaps in `v`; returns the number of contacts handled.
+    pub fn resolve(&self, v: &mut Particles) -> usize {
+        match *self {
+            Collisions::Off     => 0,
+            Collisions::Merge   => merge_overlapping(v),
+            Collisions::Elastic => bounce_overlapping(v),
+        }
+    }
+}
+
+
+// calls `f(i, j)` for every overlapping pair, `i` before `j` in x order
+fn for_each_contact<F>(v: &Particles, mut f: F) where F: FnMut(usize, usize) {
+    let mut order: Vec<usize> = (0..v.len()).collect();
+    let radius = |i: usize| 0.5*diameter(v.m[i]);
+    let left = |i: usize| v.px[i] - radius(i);
+    order.sort_unstable_by(|&a, &b| left(a).partial_cmp(&left(b)).unwrap_or(::std::cmp::Ordering::Equal));
+
+    for (k, &i) in order.iter().enumerate() {
+        let ra = radius(i);
+
+        for &j in &order[k + 1..] {
+            let rb = radius(j);
+            if left(j) > v.px[i] + ra { break; }
+
+            let (dx, dy) = (v.px[j] - v.px[i], v.py[j] - v.py[i]);
+            if dx*dx + dy*dy < (ra + rb)*(ra + rb) { f(i, j); }
+        }
+    }
+}
+
+
+/// Merges overlapping nodes, conserving mass, charge and momentum.
+/// The merged node sits at the center of mass. Charge is summed as it enters
+/// the Coulomb force (`c`), so the far field of the pair is unchanged. Each
+/// node takes part in at most one merge per call, so a cluster of touching
+/// nodes coalesces over the following steps.
+pub fn merge_overlapping(v: &mut Particles) -> usize {
+    let mut pairs = Vec::new();
+    for_each_contact(v, |i, j| pairs.push((i, j)));
+
+    let mut alive = vec![true; v.len()];
+    let mut used  = vec![false; v.len()]; // merged this call, its position and mass are already moved
+    let mut merged = 0;
+
+    for (i, j) in pairs {
+        if used[i] || used[j] { continue; }
+
+        let (a, b) = (v.node(i), v.node(j));
+        let m = a.m + b.m;
+        v.set(i, Node {
+            id: a.id, // the merged node goes on as `a`
+            m,
+            c:  a.c + b.c,
+            px: (a.m*a.px + b.m*b.px)/m, py: (a.m*a.py + b.m*b.py)/m,
+            vx: (a.m*a.vx + b.m*b.vx)/m, vy: (a.m*a.vy + b.m*b.vy)/m,
+            ax: (a.fx + b.fx)/m, ay: (a.fy + b.fy)/m,
+            fx: a.fx + b.fx, fy: a.fy + b.fy,
+        });
+
+        alive[j] = false;
+        used[i] = true;
+        used[j] = true;
+        merged += 1;
+    }
+
+    if merged > 0 { v.retain(&alive); }
+
+    merged
+}
+
+
+/// Elastic bounce of approaching overlapping nodes, then pushes them apart
+/// so they do not stick. Momentum and kinetic energy are conserved. Every
+/// pair pushed apart is counted, separating ones included.
+pub fn bounce_overlapping(v: &mut Particles) -> usize {
+    let mut pairs = Vec::new();
+    for_each_contact(v, |i, j| pairs.push((i, j)));
+
+    let mut bounced = 0;
+
+    for (i, j) in pairs {
+        let (a, b) = (v.node(i), v.node(j));
+        let (dx, dy) = (b.px - a.px, b.py - a.py);
+        let d = (dx*dx + dy*dy).sqrt();
+        if d == 0.0 { continue; }
+        let nv = (dx/d, dy/d); // contact normal, a -> b
+
+        let overlap = 0.5*(a.get_diameter_f32() + b.get_diameter_f32()) - d;
+        let (wa, wb) = (b.m/(a.m + b.m), a.m/(a.m + b.m));
+
+        // separating positions, lighter node moves more
+        v.px[i] -= nv.0*overlap*wa;  v.py[i] -= nv.1*overlap*wa;
+        v.px[j] += nv.0*overlap*wb;  v.py[j] += nv.1*overlap*wb;
+        bounced += 1;
+
+        // relative normal velocity, only approaching pairs bounce
+        let vn = (b.vx - a.vx)*nv.0 + (b.vy - a.vy)*nv.1;
+        if vn >= 0.0 { continue; }
+
+        let jn = 2.0*vn/(a.m + b.m); // impulse per unit mass product
+        v.vx[i] += jn*b.m*nv.0;  v.vy[i] += jn*b.m*nv.1;
+        v.vx[j] -= jn*a.m*nv.0;  v.vy[j] -= jn*a.m*nv.1;
+    }
+
+    bounced
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_conserves_mass_charge_momentum() {
+        let mut v = Particles::from_nodes(&[Node::new(0.0, 0.0,  2.0, 0.0, 10.0,  5.0),
+                                            Node::new(0.5, 0.0, -1.0, 1.0, 20.0, -2.0),
+                                            Node::new(9.0, 9.0,  0.0, 0.0,  1.0,  0.0)]);
+
+        assert_eq!(Collisions::Merge.resolve(&mut v), 1);
+        assert_eq!(v.len(), 2);
+        assert_eq!(v.m[1], 1.0);
+
+        let n = v.node(0);
+        assert_eq!((n.m, n.c), (30.0, 3.0));
+        assert!((n.m*n.vx - 0.0).abs() < 1e-5 && (n.m*n.vy - 20.0).abs() < 1e-5);
+        assert!((n.px - 1.0/3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn each_node_merges_once_per_call() {
+        // a row of touching nodes: 0 takes 1, then 2 may not merge into the grown 0
+        let mut v = Particles::from_nodes(&[Node::new(0.0, 0.0, 0.0, 0.0, 10.0, 0.0),
+                                            Node::new(0.6, 0.0, 0.0, 0.0, 10.0, 0.0),
+                                            Node::new(1.2, 0.0, 0.0, 0.0, 10.0, 0.0)]);
+
+        assert_eq!(Collisions::Merge.resolve(&mut v), 1);
+        assert_eq!(v.len(), 2);
+        assert_eq!(Collisions::Merge.resolve(&mut v), 1);
+        assert_eq!((v.len(), v.m[0]), (1, 30.0));
+    }
+
+    #[test]
+    fn elastic_bounce_swaps_equal_mass_velocities() {
+        let mut v = Particles::from_nodes(&[Node::new(0.0, 0.0,  1.0, 0.0, 10.0, 0.0),
+                                            Node::new(0.8, 0.0, -1.0, 0.0, 10.0, 0.0)]);
+
+        assert_eq!(Collisions::Elastic.resolve(&mut v), 1);
+        assert_eq!((v.vx[0], v.vx[1]), (-1.0, 1.0));
+        assert!(v.px[1] - v.px[0] >= 1.0 - 1e-6);
+    }
+    #[test]
+    fn separating_overlap_is_pushed_apart_and_counted() {
+        let mut v = Particles::from_nodes(&[Node::new(0.0, 0.0, -1.0, 0.0, 10.0, 0.0),
+                                            Node::new(0.8, 0.0,  1.0, 0.0, 10.0, 0.0)]);
+
+        // no bounce, but the positions moved, so it counts as a contact
+        assert_eq!(Collisions::Elastic.resolve(&mut v), 1);
+        assert_eq!((v.vx[0], v.vx[1]), (-1.0, 1.0));
+        assert!(v.px[0] < 0.0 && v.px[1] > 0.8);
+    }
+}
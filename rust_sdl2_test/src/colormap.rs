@@ -0,0 +1,107 @@
+//! Mapping of node quantities to colors, for renderers.
+
+use node::Node;
+
+
+/// Color scale over `[0, 1]`.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Colormap {
+    #[default]
+    Viridis,    // perceptually uniform, dark blue to yellow
+    Heat,       // black, red, yellow, white
+    Diverging,  // blue, light gray, red; for signed quantities
+}
+
+// control points, evenly spaced over [0, 1]
+const VIRIDIS: [(u8, u8, u8); 9] = [(68, 1, 84), (71, 44, 122), (59, 81, 139), (44, 113, 142), (33, 144, 141),
+                                    (39, 173, 129), (92, 200, 99), (170, 220, 50), (253, 231, 37)];
+const HEAT: [(u8, u8, u8); 5] = [(0, 0, 0), (180, 0, 0), (255, 120, 0), (255, 230, 60), (255, 255, 255)];
+const DIVERGING: [(u8, u8, u8); 5] = [(59, 76, 192), (141, 176, 254), (221, 221, 221), (244, 154, 123), (180, 4, 38)];
+
+impl Colormap {
+    /// Color at `t`, clamped to `[0, 1]`.
+    pub fn color(&self, t: f32) -> (u8, u8, u8) {
+        let points: &[(u8, u8, u8)] = match *self {
+            Colormap::Viridis   => &VIRIDIS,
+            Colormap::Heat      => &HEAT,
+            Colormap::Diverging => &DIVERGING,
+        };
+
+        let x = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) }*(points.len() - 1) as f32;
+        let i = (x.floor() as usize).min(points.len() - 2);
+        let f = x - i as f32;
+        let (a, b) = (points[i], points[i + 1]);
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32)*f).round() as u8;
+
+        (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+    }
+}
+
+
+/// Node quantity mapped to color.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Field {
+    Speed,        // |v|
+    Force,        // |f|
+    Acceleration, // |a|
+    Mass,         // m
+    Charge,       // c, signed
+}
+
+impl Field {
+    pub fn value(&self, n: &Node) -> f32 {
+        match *self {
+            Field::Speed        => (n.vx*n.vx + n.vy*n.vy).sqrt(),
+            Field::Force        => (n.fx*n.fx + n.fy*n.fy).sqrt(),
+            Field::Acceleration => (n.ax*n.ax + n.ay*n.ay).sqrt(),
+            Field::Mass         => n.m,
+            Field::Charge       => n.c,
+        }
+    }
+
+    /// Range of the field over `nodes`; symmetric around zero for charge,
+    /// so neutral nodes sit in the middle of a diverging map.
+    pub fn range(&self, nodes: &[Node]) -> (f32, f32) {
+        let (lo, hi) = nodes.iter().map(|n| self.value(n))
+            .fold((f32::MAX, f32::MIN), |(lo, hi), x| (lo.min(x), hi.max(x)));
+        if lo > hi { return (0.0, 1.0); }
+
+        match *self {
+            Field::Charge => { let m = lo.abs().max(hi.abs()); (-m, m) },
+            _ => (lo, hi),
+        }
+    }
+
+    /// Position of `n` within `range`, in `[0, 1]`.
+    pub fn normalize(&self, n: &Node, range: (f32, f32)) -> f32 {
+        let span = range.1 - range.0;
+        if span > 0.0 { (self.value(n) - range.0)/span } else { 0.5 }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints_and_interpolation() {
+        assert_eq!(Colormap::Viridis.color(0.0), (68, 1, 84));
+        assert_eq!(Colormap::Viridis.color(2.0), (253, 231, 37));
+        assert_eq!(Colormap::Heat.color(0.5), (255, 120, 0));
+        assert_eq!(Colormap::Heat.color(0.125), (90, 0, 0));
+        assert_eq!(Colormap::Diverging.color(0.5), (221, 221, 221));
+    }
+
+    #[test]
+    fn charge_range_is_symmetric() {
+        let v = vec![Node::new(0.0, 0.0, 3.0, 4.0, 10.0, 5.0),
+                     Node::new(1.0, 0.0, 0.0, 0.0, 20.0, -2.0)];
+
+        assert_eq!(Field::Speed.value(&v[0]), 5.0);
+        assert_eq!(Field::Mass.range(&v), (10.0, 20.0));
+        assert_eq!(Field::Charge.range(&v), (-5.0, 5.0));
+        assert_eq!(Field::Charge.normalize(&v[1], (-5.0, 5.0)), 0.3);
+        assert_eq!(Field::Speed.range(&[]), (0.0, 1.0));
+    }
+}
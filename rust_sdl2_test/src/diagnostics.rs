@@ -0,0 +1,159 @@
+//! Conserved quantities of the 2D simulation and their drift over time.
+
+use std::fmt;
+
+use node::Node;
+use world::{pair_potential, Constants};
+use force::Law;
+use boundary::Boundary;
+
+
+/// Snapshot of conserved quantities, computed on demand (potentials are O(n^2)).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Diagnostics {
+    pub n:         usize,      // number of nodes measured
+    pub kinetic:   f64,        // sum of m*v^2/2
+    pub potential: f64,        // potential energy of all force laws
+    pub momentum:  (f64, f64), // sum of m*v
+    pub angular:   f64,        // sum of m*(x*vy - y*vx), around the origin
+}
+
+impl Diagnostics {
+    pub fn measure(nodes: &[Node]) -> Diagnostics {
+        Diagnostics::measure_with(nodes, Boundary::Open, &Constants::default().laws())
+    }
+
+    /// Like `measure`, with the world's force laws; potentials use the nearest
+    /// periodic image when `boundary` wraps.
+    pub fn measure_with(nodes: &[Node], boundary: Boundary, laws: &[Law]) -> Diagnostics {
+        let mut d = Diagnostics { n: nodes.len(), kinetic: 0.0, potential: 0.0,
+                                  momentum: (0.0, 0.0), angular: 0.0 };
+
+        for (i, n) in nodes.iter().enumerate() {
+            let (m, vx, vy) = (n.m as f64, n.vx as f64, n.vy as f64);
+            d.kinetic    += 0.5*m*(vx*vx + vy*vy);
+            d.momentum.0 += m*vx;
+            d.momentum.1 += m*vy;
+            d.angular    += m*(n.px as f64*vy - n.py as f64*vx);
+
+            // every pair once
+            for o in &nodes[i + 1..] {
+                d.potential += pair_potential(n, o, boundary, laws) as f64;
+            }
+        }
+
+        d
+    }
+
+    pub fn total_energy(&self) -> f64 { self.kinetic + self.potential }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "n={} E={:.6e} (K={:.6e} U={:.6e}) P=({:.6e}, {:.6e}) L={:.6e}",
+               self.n, self.total_energy(), self.kinetic, self.potential,
+               self.momentum.0, self.momentum.1, self.angular)
+    }
+}
+
+
+/// Drift of conserved quantities relative to a reference measurement.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Drift {
+    pub time:     f32,
+    pub energy:   f64, // relative: (E - E0)/|E0|
+    pub momentum: f64, // absolute: |P - P0|
+    pub angular:  f64, // relative: (L - L0)/|L0|
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "t={:.3} dE/E0={:+.3e} |dP|={:.3e} dL/L0={:+.3e}",
+               self.time, self.energy, self.momentum, self.angular)
+    }
+}
+
+
+/// Records measurements over a run. The reference is taken again whenever the
+/// number of nodes changes, since emitters add energy and momentum.
+#[derive(Clone, Debug, Default)]
+pub struct DriftLog {
+    reference: Option<Diagnostics>,
+    history:   Vec<(f32, Diagnostics)>,
+}
+
+impl DriftLog {
+    pub fn new() -> DriftLog { DriftLog::default() }
+
+    /// Stores `d` measured at `time` and returns its drift against the reference.
+    pub fn record(&mut self, time: f32, d: Diagnostics) -> Drift {
+        let reference = match self.reference {
+            Some(r) if r.n == d.n => r,
+            _ => { self.reference = Some(d); d }
+        };
+        self.history.push((time, d));
+
+        let rel = |x: f64, x0: f64| if x0 != 0.0 { (x - x0)/x0.abs() } else { x - x0 };
+        let dp  = (d.momentum.0 - reference.momentum.0, d.momentum.1 - reference.momentum.1);
+
+        Drift {
+            time,
+            energy:   rel(d.total_energy(), reference.total_energy()),
+            momentum: (dp.0*dp.0 + dp.1*dp.1).sqrt(),
+            angular:  rel(d.angular, reference.angular),
+        }
+    }
+
+    pub fn reference(&self) -> Option<&Diagnostics> { self.reference.as_ref() }
+
+    pub fn history(&self) -> &[(f32, Diagnostics)] { &self.history }
+
+    pub fn last(&self) -> Option<&Diagnostics> { self.history.last().map(|h| &h.1) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrator::Integrator;
+    use world::{World, node_force};
+
+    #[test]
+    fn potential_matches_force_by_finite_difference() {
+        let a = Node::new(0.0, 0.0, 0.0, 0.0, 10.0, 3.0);
+        for &x in &[2.0f32, 3.5, 6.0, 20.0] {
+            let h = 1e-2;
+            let k = Constants::default().laws();
+            let (u1, u2) = (pair_potential(&a, &Node { px: x - h, ..a }, Boundary::Open, &k[..]),
+                            pair_potential(&a, &Node { px: x + h, ..a }, Boundary::Open, &k[..]));
+            // force on `a` towards +x equals dU/dx of the separation
+            let f = node_force(&a, &[Node { px: x, ..a }], Boundary::Open, &k[..]).0;
+            let du = (u2 - u1)/(2.0*h);
+            assert!((f - du).abs() <= 1e-2*f.abs().max(1.0), "x={} f={} dU={}", x, f, du);
+        }
+    }
+
+    #[test]
+    fn verlet_conserves_energy_and_momentum() {
+        let mut w = World::new();
+        w.set_threads(2);
+        w.set_integrator(Integrator::VelocityVerlet);
+        w.add_node(Node::new(-25.0,  0.0, 0.0, -1.0, 10.0,  0.5));
+        w.add_node(Node::new( 25.0,  0.0, 0.0,  1.0, 10.0, -0.5));
+        w.add_node(Node::new(  0.0, 60.0, 0.5,  0.0,  1.0,  0.0));
+
+        let mut log = DriftLog::new();
+        log.record(w.time(), Diagnostics::measure(&w.nodes()));
+        let mut drift = None;
+        for _ in 0..1000 {
+            w.step(0.1);
+            drift = Some(log.record(w.time(), Diagnostics::measure(&w.nodes())));
+        }
+
+        let drift = drift.unwrap();
+        assert!(drift.energy.abs() < 1e-3, "{}", drift);
+        assert!(drift.momentum < 1e-2, "{}", drift);
+        assert!(drift.angular.abs() < 1e-3, "{}", drift);
+        assert_eq!(log.history().len(), 1001);
+    }
+}
@@ -0,0 +1,302 @@
+//! Pairwise force laws, combinable and configurable per scene.
+//!
+//! A law gives the force between two nodes as a scale `s` of their separation:
+//! the force on `a` from `b` is `s*(b - a)`, so positive values attract. Laws
+//! with a `dthr` clamp the distance there; inside it the force falls linearly
+//! to zero and the potential is a parabola, so close pairs stay finite.
+//!
+//! ```toml
+//! [[force]]
+//! kind = "Gravity"
+//! g    = 10.0
+//! dthr = 4.0
+//!
+//! [[force]]
+//! kind    = "LennardJones"
+//! epsilon = 1.0
+//! sigma   = 4.0
+//! cutoff  = 10.0
+//! ```
+
+use node::Node;
+
+
+pub trait ForceLaw {
+    /// Scale of the force on `a` from `b` at distance `d` (see the module docs).
+    fn force(&self, a: &Node, b: &Node, d: f32) -> f32;
+
+    /// Potential energy of the pair at distance `d`, consistent with `force`.
+    fn potential(&self, a: &Node, b: &Node, d: f32) -> f32;
+
+    /// Distance beyond which the force is zero, for short-range laws. Long-range
+    /// laws (`None`) must depend on the nodes only through `m` or `c`, so that
+    /// far groups of nodes can be replaced by their mass and charge monopoles.
+    fn cutoff(&self) -> Option<f32> { None }
+}
+
+
+// `s` at `max(d, dmin)`: the force falls linearly inside `dmin`
+fn clamped<F: Fn(f32) -> f32>(d: f32, dmin: f32, s: F) -> f32 {
+    s(d.max(dmin))
+}
+
+// potential of `clamped`, from `u` and `s` outside `dmin`
+fn clamped_potential<U: Fn(f32) -> f32, F: Fn(f32) -> f32>(d: f32, dmin: f32, u: U, s: F) -> f32 {
+    if d >= dmin { u(d) } else { u(dmin) - 0.5*s(dmin)*(dmin*dmin - d*d) }
+}
+
+
+/// Newtonian gravity, `g*m1*m2/d^2`, attractive.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gravity {
+    pub g:    f32,
+    pub dthr: f32,
+}
+
+impl ForceLaw for Gravity {
+    fn force(&self, a: &Node, b: &Node, d: f32) -> f32 {
+        clamped(d, self.dthr, |d| self.g*a.m*b.m/(d*d*d))
+    }
+
+    fn potential(&self, a: &Node, b: &Node, d: f32) -> f32 {
+        let k = self.g*a.m*b.m;
+        clamped_potential(d, self.dthr, |d| -k/d, |d| k/(d*d*d))
+    }
+}
+
+
+/// Electrostatics, `k*c1*c2/d^2`, like charges repel.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Coulomb {
+    pub k:    f32,
+    pub dthr: f32,
+}
+
+impl ForceLaw for Coulomb {
+    fn force(&self, a: &Node, b: &Node, d: f32) -> f32 {
+        clamped(d, self.dthr, |d| -self.k*a.c*b.c/(d*d*d))
+    }
+
+    fn potential(&self, a: &Node, b: &Node, d: f32) -> f32 {
+        let k = self.k*a.c*b.c;
+        clamped_potential(d, self.dthr, |d| k/d, |d| -k/(d*d*d))
+    }
+}
+
+
+/// Lennard-Jones 12-6 potential, `4*epsilon*((sigma/d)^12 - (sigma/d)^6)`, the same
+/// for every pair; zero beyond `cutoff`. The distance is clamped at `sigma/2`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LennardJones {
+    pub epsilon: f32,
+    pub sigma:   f32,
+    pub cutoff:  f32,
+}
+
+impl LennardJones {
+    fn s(&self, d: f32) -> f32 {
+        let sr6 = (self.sigma/d).powi(6);
+        -24.0*self.epsilon*(2.0*sr6*sr6 - sr6)/(d*d)
+    }
+
+    fn u(&self, d: f32) -> f32 {
+        let sr6 = (self.sigma/d).powi(6);
+        4.0*self.epsilon*(sr6*sr6 - sr6)
+    }
+}
+
+impl ForceLaw for LennardJones {
+    fn force(&self, _: &Node, _: &Node, d: f32) -> f32 {
+        if d > self.cutoff { return 0.0; }
+        clamped(d, 0.5*self.sigma, |d| self.s(d))
+    }
+
+    fn potential(&self, _: &Node, _: &Node, d: f32) -> f32 {
+        if d > self.cutoff { return 0.0; }
+        clamped_potential(d, 0.5*self.sigma, |d| self.u(d), |d| self.s(d))
+    }
+
+    fn cutoff(&self) -> Option<f32> { Some(self.cutoff) }
+}
+
+
+/// Screened Coulomb (Yukawa), `k*c1*c2*exp(-d/lambda)/d` potential. With a
+/// `cutoff` (a few `lambda`) it is zero beyond it and counts as short-range.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Yukawa {
+    pub k:      f32,
+    pub lambda: f32, // screening length
+    pub dthr:   f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cutoff: Option<f32>, // none means long-range
+}
+
+impl ForceLaw for Yukawa {
+    fn force(&self, a: &Node, b: &Node, d: f32) -> f32 {
+        if self.cutoff.is_some_and(|c| d > c) { return 0.0; }
+        let k = self.k*a.c*b.c;
+        clamped(d, self.dthr, |d| -k*(-d/self.lambda).exp()*(1.0/d + 1.0/self.lambda)/(d*d))
+    }
+
+    fn potential(&self, a: &Node, b: &Node, d: f32) -> f32 {
+        if self.cutoff.is_some_and(|c| d > c) { return 0.0; }
+        let k = self.k*a.c*b.c;
+        clamped_potential(d, self.dthr, |d| k*(-d/self.lambda).exp()/d,
+                          |d| -k*(-d/self.lambda).exp()*(1.0/d + 1.0/self.lambda)/(d*d))
+    }
+
+    fn cutoff(&self) -> Option<f32> { self.cutoff }
+}
+
+
+/// Plummer-softened gravity, `g*m1*m2*d/(d^2 + eps^2)^(3/2)`; smooth, no clamp needed.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Plummer {
+    pub g:   f32,
+    pub eps: f32, // softening length
+}
+
+impl ForceLaw for Plummer {
+    fn force(&self, a: &Node, b: &Node, d: f32) -> f32 {
+        self.g*a.m*b.m/(d*d + self.eps*self.eps).powf(1.5)
+    }
+
+    fn potential(&self, a: &Node, b: &Node, d: f32) -> f32 {
+        -self.g*a.m*b.m/(d*d + self.eps*self.eps).sqrt()
+    }
+}
+
+
+/// Any of the built-in laws, as configured in scenes and snapshots.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Law {
+    Gravity(Gravity),
+    Coulomb(Coulomb),
+    LennardJones(LennardJones),
+    Yukawa(Yukawa),
+    Plummer(Plummer),
+}
+
+impl Law {
+    fn as_law(&self) -> &dyn ForceLaw {
+        match *self {
+            Law::Gravity(ref l)      => l,
+            Law::Coulomb(ref l)      => l,
+            Law::LennardJones(ref l) => l,
+            Law::Yukawa(ref l)       => l,
+            Law::Plummer(ref l)      => l,
+        }
+    }
+}
+
+impl ForceLaw for Law {
+    fn force(&self, a: &Node, b: &Node, d: f32) -> f32 { self.as_law().force(a, b, d) }
+
+    fn potential(&self, a: &Node, b: &Node, d: f32) -> f32 { self.as_law().potential(a, b, d) }
+
+    fn cutoff(&self) -> Option<f32> { self.as_law().cutoff() }
+}
+
+
+/// Laws combine by summing; a combination is short-range only if all its laws are.
+impl<L: ForceLaw> ForceLaw for [L] {
+    fn force(&self, a: &Node, b: &Node, d: f32) -> f32 {
+        self.iter().map(|l| l.force(a, b, d)).sum()
+    }
+
+    fn potential(&self, a: &Node, b: &Node, d: f32) -> f32 {
+        self.iter().map(|l| l.potential(a, b, d)).sum()
+    }
+
+    fn cutoff(&self) -> Option<f32> {
+        self.iter().map(|l| l.cutoff()).try_fold(0.0f32, |rc, c| c.map(|c| rc.max(c)))
+    }
+}
+
+
+/// Force on `a` from `b` (already at its nearest image) under `law`.
+/// Coincident nodes exert no force on each other.
+pub fn pair_force<L: ForceLaw + ?Sized>(a: &Node, b: &Node, law: &L) -> (f32, f32) {
+    let dnm = (b.px - a.px, b.py - a.py);            // distance vector
+    let d   = (dnm.0*dnm.0 + dnm.1*dnm.1).sqrt();   // distance scalar
+    if d == 0.0 { return (0.0, 0.0); }
+
+    let s = law.force(a, b, d);
+    (s*dnm.0, s*dnm.1)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn laws() -> Vec<Law> {
+        vec![Law::Gravity(Gravity { g: 10.0, dthr: 4.0 }),
+             Law::Coulomb(Coulomb { k: 10.0, dthr: 4.0 }),
+             Law::LennardJones(LennardJones { epsilon: 2.0, sigma: 4.0, cutoff: 30.0 }),
+             Law::Yukawa(Yukawa { k: 10.0, lambda: 8.0, dthr: 4.0, cutoff: None }),
+             Law::Plummer(Plummer { g: 10.0, eps: 3.0 })]
+    }
+
+    #[test]
+    fn force_is_minus_potential_gradient() {
+        let a = Node::new(0.0, 0.0, 0.0, 0.0, 10.0, 3.0);
+        let b = Node::new(0.0, 0.0, 0.0, 0.0, 20.0, -2.0);
+        let h = 1e-2;
+
+        for law in laws() {
+            for &x in &[1.0f32, 3.5, 4.5, 6.0, 20.0] {
+                let u = |x: f32| law.potential(&a, &b, x) as f64;
+                // force on `a` towards +x (where `b` is) equals dU/dx of the separation
+                let f  = pair_force(&a, &Node { px: x, ..b }, &law).0 as f64;
+                let du = (u(x + h) - u(x - h))/(2.0*h as f64);
+                assert!((f - du).abs() <= 2e-2*f.abs().max(1e-2), "{:?} x={} f={} dU={}", law, x, f, du);
+            }
+        }
+    }
+
+    #[test]
+    fn combination_sums_and_keeps_long_range() {
+        let (a, b) = (Node::new(0.0, 0.0, 0.0, 0.0, 1.0, 1.0), Node::new(5.0, 0.0, 0.0, 0.0, 1.0, 1.0));
+        let all = laws();
+        let sum: f32 = all.iter().map(|l| l.force(&a, &b, 5.0)).sum();
+
+        assert_eq!(all[..].force(&a, &b, 5.0), sum);
+        assert_eq!(all[..].cutoff(), None);
+        assert_eq!(all[2..3].cutoff(), Some(30.0));
+        assert!(all[2].force(&a, &b, 3.0) < 0.0 && all[2].force(&a, &b, 6.0) > 0.0); // LJ: repulsive core
+    }
+
+    #[test]
+    fn yukawa_with_cutoff_is_short_range() {
+        let (a, b) = (Node::new(0.0, 0.0, 0.0, 0.0, 1.0, 1.0), Node::new(5.0, 0.0, 0.0, 0.0, 1.0, 1.0));
+        let y = Yukawa { k: 10.0, lambda: 2.0, dthr: 1.0, cutoff: Some(8.0) };
+
+        assert_eq!(y.cutoff(), Some(8.0));
+        assert!(y.force(&a, &b, 5.0) < 0.0);
+        assert_eq!((y.force(&a, &b, 9.0), y.potential(&a, &b, 9.0)), (0.0, 0.0));
+    }
+
+    #[test]
+    fn laws_parse_from_toml() {
+        #[derive(Deserialize)]
+        struct Doc { force: Vec<Law> }
+
+        let doc: Doc = ::toml::from_str(r#"
+            [[force]]
+            kind = "Plummer"
+            g    = 1.0
+            eps  = 0.5
+            [[force]]
+            kind   = "Yukawa"
+            k      = 2.0
+            lambda = 3.0
+            dthr   = 1.0
+            cutoff = 12.0
+        "#).unwrap();
+
+        assert_eq!(doc.force, vec![Law::Plummer(Plummer { g: 1.0, eps: 0.5 }),
+                                   Law::Yukawa(Yukawa { k: 2.0, lambda: 3.0, dthr: 1.0, cutoff: Some(12.0) })]);
+    }
+}
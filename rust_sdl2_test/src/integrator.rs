@@ -0,0 +1,243 @@
+//! Time integration schemes for node updates.
+
+use std::sync::Arc;
+
+use node::Node;
+use soa::Particles;
+use magnetic::Magnetic;
+
+
+/// Scheme used to advance positions and velocities by one step.
+#[derive(Copy, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub enum Integrator {
+    Euler,              // explicit: x += v*dt, v += a*dt
+    #[default]
+    SemiImplicitEuler,  // symplectic: v += a*dt, x += v*dt (the original update)
+    VelocityVerlet,     // kick-drift-kick leapfrog, 1 force evaluation per step
+    Rk4,                // classic Runge-Kutta, 4 force evaluations per step
+    Boris,              // kick-rotate-kick-drift, exact gyration in a magnetic field
+}
+
+impl Integrator {
+    /// Advances `p` by `dt`. `eval` fills the forces for a given state of all nodes,
+    /// the Lorentz force of `magnetic` is added to them (Boris rotates instead).
+    /// Afterwards `fx, fy, ax, ay` hold the last forces evaluated for each node.
+    /// `accels_valid` tells whether `ax, ay` already belong to the current positions
+    /// (velocity Verlet then reuses them instead of evaluating the forces twice).
+    /// States are passed to `eval` shared, so force workers can read them without
+    /// a copy; they must be released again before `eval` returns.
+    pub fn step<F>(&self, p: &mut Arc<Particles>, forces: &mut Vec<(f32, f32)>, dt: f32, magnetic: Option<Magnetic>,
+                   accels_valid: bool, eval: &mut F)
+            where F: FnMut(&Arc<Particles>, &mut Vec<(f32, f32)>) {
+        let mut with_lorentz = |state: &Arc<Particles>, forces: &mut Vec<(f32, f32)>| {
+            eval(state, forces);
+            if let Some(b) = magnetic {
+                for (i, fv) in forces.iter_mut().enumerate() {
+                    let fl = b.lorentz(&state.node(i));
+                    fv.0 += fl.0;  fv.1 += fl.1;
+                }
+            }
+        };
+
+        match *self {
+            Integrator::Euler => {
+                with_lorentz(p, forces);
+                let v = Arc::make_mut(p);
+                set_forces(v, forces);
+                for i in 0..v.len() {
+                    v.px[i] += v.vx[i]*dt;  v.py[i] += v.vy[i]*dt;
+                    v.vx[i] += v.ax[i]*dt;  v.vy[i] += v.ay[i]*dt;
+                }
+            },
+            Integrator::SemiImplicitEuler => {
+                with_lorentz(p, forces);
+                let v = Arc::make_mut(p);
+                set_forces(v, forces);
+                for i in 0..v.len() {
+                    v.vx[i] += v.ax[i]*dt;  v.vy[i] += v.ay[i]*dt;
+                    v.px[i] += v.vx[i]*dt;  v.py[i] += v.vy[i]*dt;
+                }
+            },
+            Integrator::VelocityVerlet => {
+                // the first kick reuses the accelerations of the previous step,
+                // unless nodes were added or moved since
+                let h = 0.5*dt;
+                if !accels_valid {
+                    with_lorentz(p, forces);
+                    set_forces(Arc::make_mut(p), forces);
+                }
+                {
+                    let v = Arc::make_mut(p);
+                    for i in 0..v.len() {
+                        v.vx[i] += v.ax[i]*h;   v.vy[i] += v.ay[i]*h;
+                        v.px[i] += v.vx[i]*dt;  v.py[i] += v.vy[i]*dt;
+                    }
+                }
+
+                with_lorentz(p, forces);
+                let v = Arc::make_mut(p);
+                set_forces(v, forces);
+                for i in 0..v.len() {
+                    v.vx[i] += v.ax[i]*h;   v.vy[i] += v.ay[i]*h;
+                }
+            },
+            Integrator::Rk4 => rk4_step(p, forces, dt, &mut with_lorentz),
+            Integrator::Boris => {
+                eval(p, forces);
+                boris_push(Arc::make_mut(p), forces, dt, magnetic);
+            },
+        }
+    }
+}
+
+
+// stores `forces` and the accelerations they give
+fn set_forces(v: &mut Particles, forces: &[(f32, f32)]) {
+    for (i, fv) in forces.iter().enumerate() {
+        v.fx[i] = fv.0;
+        v.fy[i] = fv.1;
+        v.ax[i] = fv.0/v.m[i];
+        v.ay[i] = fv.1/v.m[i];
+    }
+}
+
+
+// half kick by `forces`, rotation by the magnetic field, half kick, drift
+fn boris_push(v: &mut Particles, forces: &[(f32, f32)], dt: f32, magnetic: Option<Magnetic>) {
+    let h = 0.5*dt;
+    set_forces(v, forces);
+    for i in 0..v.len() {
+        let (mut vx, mut vy) = (v.vx[i] + v.ax[i]*h, v.vy[i] + v.ay[i]*h);
+
+        if let Some(b) = magnetic {
+            let t = v.c[i]/v.m[i]*b.bz(v.px[i], v.py[i])*h;
+            let s = 2.0*t/(1.0 + t*t);
+            let (wx, wy) = (vx + vy*t, vy - vx*t); // v + v x t
+            vx += wy*s;                            // v + (v + v x t) x s
+            vy -= wx*s;
+        }
+
+        v.vx[i] = vx + v.ax[i]*h;  v.vy[i] = vy + v.ay[i]*h;
+        v.px[i] += v.vx[i]*dt;     v.py[i] += v.vy[i]*dt;
+    }
+}
+
+
+fn rk4_step<F>(p: &mut Arc<Particles>, forces: &mut Vec<(f32, f32)>, dt: f32, eval: &mut F)
+        where F: FnMut(&Arc<Particles>, &mut Vec<(f32, f32)>) {
+    let start = (**p).clone();
+    let mut stage = Arc::new(start.clone());
+    // weighted sums of derivatives: (dx, dy, dvx, dvy)
+    let mut sum = vec![(0.0f32, 0.0f32, 0.0f32, 0.0f32); start.len()];
+
+    // (fraction of dt to the next stage, weight of this stage)
+    let stages = [(0.5, 1.0), (0.5, 2.0), (1.0, 2.0), (0.0, 1.0)];
+
+    for (k, &(next, weight)) in stages.iter().enumerate() {
+        eval(&stage, forces);
+        if k == 0 { set_forces(Arc::make_mut(p), forces); }
+
+        let s = Arc::make_mut(&mut stage);
+        for (i, acc) in sum.iter_mut().enumerate() {
+            let fv = forces[i];
+            let d = (s.vx[i], s.vy[i], fv.0/s.m[i], fv.1/s.m[i]);
+
+            acc.0 += weight*d.0; acc.1 += weight*d.1;
+            acc.2 += weight*d.2; acc.3 += weight*d.3;
+
+            s.px[i] = start.px[i] + d.0*next*dt;  s.py[i] = start.py[i] + d.1*next*dt;
+            s.vx[i] = start.vx[i] + d.2*next*dt;  s.vy[i] = start.vy[i] + d.3*next*dt;
+        }
+    }
+
+    let v = Arc::make_mut(p);
+    for (i, acc) in sum.iter().enumerate() {
+        v.px[i] += acc.0*dt/6.0;  v.py[i] += acc.1*dt/6.0;
+        v.vx[i] += acc.2*dt/6.0;  v.vy[i] += acc.3*dt/6.0;
+    }
+}
+
+
+// computing accelerations, velocities, positions from forces (semi-implicit Euler)
+pub fn apply_forces(v: &mut [Node], forces: &[(f32, f32)], dt: f32) {
+    for (n, fv) in v.iter_mut().zip(forces.iter()) {
+        n.fx = fv.0;
+        n.fy = fv.1;
+
+        let av = (fv.0/n.m, fv.1/n.m);
+        n.ax = av.0;
+        n.ay = av.1;
+
+        //let kv = 1.0 - 0.001*dt;  // drag
+        let kv = 1.0;  // drag
+
+        let mut vv = (n.vx + av.0*dt, n.vy + av.1*dt);
+        vv.0 *= kv;
+        vv.1 *= kv;
+        n.vx = vv.0;
+        n.vy = vv.1;
+
+        let pv = (n.px + vv.0*dt, n.py + vv.1*dt);
+        n.px = pv.0;
+        n.py = pv.1;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::World;
+
+    // two equal masses on a circular orbit, returns (min, max) separation over ~6 orbits
+    fn orbit(integrator: Integrator) -> (f32, f32) {
+        let mut w = World::new();
+        w.set_threads(1);
+        w.set_integrator(integrator);
+        w.add_node(Node::new(-25.0, 0.0, 0.0, -1.0, 10.0, 0.0));
+        w.add_node(Node::new( 25.0, 0.0, 0.0,  1.0, 10.0, 0.0));
+
+        let (mut lo, mut hi) = (f32::MAX, f32::MIN);
+        for _ in 0..2000 {
+            w.step(0.5);
+            let (a, b) = (w.node(0), w.node(1));
+            let d = ((b.px - a.px).powi(2) + (b.py - a.py).powi(2)).sqrt();
+            lo = lo.min(d);
+            hi = hi.max(d);
+        }
+        (lo, hi)
+    }
+
+    #[test]
+    fn verlet_and_rk4_keep_circular_orbit() {
+        for &i in &[Integrator::VelocityVerlet, Integrator::Rk4] {
+            let (lo, hi) = orbit(i);
+            assert!(lo > 49.0 && hi < 51.0, "{:?}: {} .. {}", i, lo, hi);
+        }
+    }
+
+    #[test]
+    fn boris_gyrates_at_constant_speed() {
+        // c*Bz/m = 0.5: period 4*pi, radius |v|/0.5 = 20 around (0, 0)
+        for &i in &[Integrator::Boris, Integrator::Rk4] {
+            let mut w = World::new();
+            w.set_integrator(i);
+            w.set_magnetic(Some(Magnetic::Uniform { bz: 0.25 }));
+            w.add_node(Node::new(0.0, 20.0, 10.0, 0.0, 1.0, 2.0));
+
+            for _ in 0..10_000 {
+                w.step(0.01);
+                let n = w.node(0);
+                let (r, speed) = ((n.px*n.px + n.py*n.py).sqrt(), (n.vx*n.vx + n.vy*n.vy).sqrt());
+                assert!((speed - 10.0).abs() < 1e-2, "{:?}: speed {}", i, speed);
+                assert!((r - 20.0).abs() < 0.1, "{:?}: radius {}", i, r);
+            }
+        }
+    }
+
+    #[test]
+    fn explicit_euler_drifts_outwards() {
+        let (_, hi) = orbit(Integrator::Euler);
+        assert!(hi > 55.0);
+    }
+}
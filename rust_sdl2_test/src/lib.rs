@@ -1,6 +1,162 @@
+//! Headless 2D particle simulation (gravity + charge forces).
+//!
+//! The SDL2 binary is only one front-end over `World`; tests and
+//! other tools can step the simulation without opening a window.
+
+extern crate gif;
+extern crate png;
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+
+pub mod node;
+pub mod boundary;
+pub mod cellgrid;
+pub mod collision;
+pub mod colormap;
+pub mod diagnostics;
+pub mod font;
+pub mod force;
+pub mod integrator;
+pub mod magnetic;
+pub mod pool;
+pub mod quadtree;
+pub mod recorder;
+pub mod scene;
+pub mod snapshot;
+pub mod soa;
+pub mod sprite;
+pub mod timestep;
+pub mod trail;
+pub mod trajectory;
+pub mod world;
+
+pub use node::{Node, emit_node, init_nodes_vec};
+pub use boundary::Boundary;
+pub use cellgrid::CellGrid;
+pub use collision::Collisions;
+pub use colormap::{Colormap, Field};
+pub use diagnostics::{Diagnostics, Drift, DriftLog};
+pub use force::{ForceLaw, Law, Gravity, Coulomb, LennardJones, Yukawa, Plummer};
+pub use integrator::Integrator;
+pub use magnetic::Magnetic;
+pub use pool::WorkerPool;
+pub use quadtree::QuadTree;
+pub use recorder::{Recorder, RecordOptions, RecordError};
+pub use scene::{Scene, SceneError};
+pub use snapshot::{Snapshot, SnapshotError};
+pub use soa::{Particles, Kernel};
+pub use timestep::{AdaptiveDt, fit_step};
+pub use trail::Trails;
+pub use trajectory::{TrajectoryWriter, Format, Frame};
+pub use world::{World, Emitter, Solver, Constants, update_nodes_vec, seeded_rng, random_seed};
+
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn it_works() {
     }
+
+    #[test]
+    fn init_nodes_vec_builds_two_opposite_blocks() {
+        let mut v = Vec::new();
+        init_nodes_vec(&mut v, 64, &mut seeded_rng(1));
+
+        assert_eq!(v.len(), 64);
+        assert!(v[..32].iter().all(|n| n.c > 0.0));
+        assert!(v[32..].iter().all(|n| n.c < 0.0));
+    }
+
+    #[test]
+    fn two_nodes_attract_symmetrically() {
+        let mut w = World::new();
+        w.add_node(Node::new(-10.0, 0.0, 0.0, 0.0, 10.0, 0.0));
+        w.add_node(Node::new( 10.0, 0.0, 0.0, 0.0, 10.0, 0.0));
+        w.step(0.01);
+
+        let n = w.nodes();
+        assert!(n[0].fx > 0.0 && n[1].fx < 0.0);
+        assert_eq!(n[0].fx, -n[1].fx);
+        assert!(n[0].px > -10.0 && n[1].px < 10.0);
+        assert_eq!(w.steps(), 1);
+    }
+
+    #[test]
+    fn verlet_first_kick_uses_forces_of_new_nodes() {
+        let mut w = World::new();
+        w.set_integrator(Integrator::VelocityVerlet);
+        w.add_node(Node::new(-10.0, 0.0, 0.0, 0.0, 10.0, 0.0));
+        w.add_node(Node::new( 10.0, 0.0, 0.0, 0.0, 10.0, 0.0));
+        w.step(0.01);
+
+        // both half kicks act, not only the second one
+        let n = w.node(0);
+        assert!((n.vx - n.ax*0.01).abs() < 0.01*n.vx.abs());
+
+        // a node added later gets its own first kick as well
+        w.add_node(Node::new(0.0, 30.0, 0.0, 0.0, 10.0, 0.0));
+        w.step(0.01);
+        let n = w.node(2);
+        assert!(n.vy < 0.0 && (n.vy - n.ay*0.01).abs() < 0.01*n.vy.abs());
+    }
+
+    #[test]
+    fn emitters_respect_max_nodes() {
+        let mut w = World::with_max_nodes(5);
+        w.add_emitter(Emitter::new(-200.0,  32.0,  10.0,  10.0, 20.0, -10.0));
+        w.add_emitter(Emitter::new( 200.0, -32.0, -10.0, -10.0, 20.0,  10.0));
+
+        for _ in 0..10 { w.step(0.01); }
+
+        assert_eq!(w.len(), 5);
+    }
+
+    #[test]
+    fn nearest_finds_closest_node_within_radius() {
+        let mut w = World::new();
+        w.add_node(Node::new(0.0, 0.0, 0.0, 0.0, 10.0, 0.0));
+        w.add_node(Node::new(5.0, 0.0, 0.0, 0.0, 10.0, 0.0));
+
+        assert_eq!(w.nearest((3.0, 1.0), 10.0), Some(1));
+        assert_eq!(w.nearest((-1.0, 0.0), 10.0), Some(0));
+        assert_eq!(w.nearest((20.0, 0.0), 10.0), None);
+    }
+
+    #[test]
+    fn ids_stay_with_nodes_through_merges_and_emission() {
+        let mut w = World::new();
+        w.set_collisions(Collisions::Merge);
+        w.add_node(Node::new(0.0, 0.0, 0.0, 0.0, 10.0, 0.0));
+        w.add_node(Node::new(0.5, 0.0, 0.0, 0.0, 10.0, 0.0));
+        w.add_node(Node::new(50.0, 0.0, 0.0, 0.0, 10.0, 0.0));
+        w.add_emitter(Emitter::new(-200.0, 0.0, 0.0, 0.0, 1.0, 0.0));
+        w.step(0.01);
+
+        // one merge, one emission: indices shifted, ids did not
+        assert_eq!(w.particles().id, vec![1, 3, 4]);
+        assert_eq!(w.index_of(3), Some(1));
+        assert_eq!(w.index_of(2), None);
+        assert!((w.node(1).px - 50.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn thread_count_does_not_change_results() {
+        let mut v = Vec::new();
+        init_nodes_vec(&mut v, 200, &mut seeded_rng(1));
+
+        let mut w1 = World::new();
+        let mut w4 = World::new();
+        w1.set_threads(1);
+        w4.set_threads(4);
+        for n in &v { w1.add_node(*n); w4.add_node(*n); }
+
+        for _ in 0..3 { w1.step(0.01); w4.step(0.01); }
+
+        assert_eq!(w1.nodes(), w4.nodes());
+    }
 }
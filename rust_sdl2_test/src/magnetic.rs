@@ -0,0 +1,71 @@
+//! Out-of-plane magnetic field and the Lorentz force on charged nodes.
+//!
+//! The field points along z, out of the 2D plane. A node with charge `c`
+//! (the same charge the electrostatic laws use) moving with velocity `v` feels
+//! `c*(v x B)`, so it gyrates with angular frequency `c*Bz/m` (clockwise for
+//! positive `c*Bz`) without gaining energy. `Integrator::Boris` integrates this rotation exactly; the other
+//! integrators add the force to the evaluated ones.
+//!
+//! ```toml
+//! [magnetic]
+//! kind = "Gradient"
+//! bz   = 0.1
+//! gx   = 0.0005
+//! gy   = 0.0
+//! ```
+
+use node::Node;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Magnetic {
+    Uniform  { bz: f32 },                   // same everywhere
+    Gradient { bz: f32, gx: f32, gy: f32 }, // bz + gx*x + gy*y, gives grad-B drift
+}
+
+impl Magnetic {
+    /// Field strength at `(x, y)`.
+    pub fn bz(&self, x: f32, y: f32) -> f32 {
+        match *self {
+            Magnetic::Uniform  { bz }         => bz,
+            Magnetic::Gradient { bz, gx, gy } => bz + gx*x + gy*y,
+        }
+    }
+
+    /// Lorentz force on `n`.
+    pub fn lorentz(&self, n: &Node) -> (f32, f32) {
+        let qb = n.c*self.bz(n.px, n.py);
+        (qb*n.vy, -qb*n.vx)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use soa::Particles;
+    use collision::Collisions;
+
+    #[test]
+    fn force_is_perpendicular_to_velocity() {
+        let n = Node::new(10.0, 0.0, 3.0, 4.0, 2.0, 0.5);
+        let b = Magnetic::Gradient { bz: 1.0, gx: 0.1, gy: 0.0 };
+        let f = b.lorentz(&n);
+
+        assert_eq!(b.bz(10.0, 5.0), 2.0);
+        assert_eq!(f, (4.0, -3.0));
+        assert_eq!(f.0*n.vx + f.1*n.vy, 0.0);
+    }
+
+    #[test]
+    fn merging_keeps_the_lorentz_force() {
+        let (a, b) = (Node::new(0.0, 0.0, 3.0, 4.0, 2.0, 0.5), Node::new(0.5, 0.0, 3.0, 4.0, 6.0, 1.5));
+        let mut v = Particles::from_nodes(&[a, b]);
+        Collisions::Merge.resolve(&mut v);
+
+        let field = Magnetic::Uniform { bz: 2.0 };
+        let (fa, fb, fm) = (field.lorentz(&a), field.lorentz(&b), field.lorentz(&v.node(0)));
+        assert_eq!((v.len(), fm), (1, (fa.0 + fb.0, fa.1 + fb.1)));
+    }
+}
@@ -1,234 +1,399 @@
-/// Author: Michal Bokiniec
-///
-/// Simple toy project to learn basics of Rust + SDL2.
-/// It presents a window, in which particles interact with each other
-/// with gravity and charge forces. Every particle intracts with all others, so 
-/// the complexity is a cube of particle number (smooth up to ~512 p.). 
-/// It spawns many threads (as many particles there is), 
-/// in each thread a force for a particle is computed. 
-/// Resulting forces from threads are collected to vector.
-/// This vector is used to compute accelerations, velocities, 
-/// and positions of particles (singlethreaded).
-///
-/// cargo build && cargo run
-
+//! Author: Michal Bokiniec
+//!
+//! Simple toy project to learn basics of Rust + SDL2.
+//! It presents a window, in which particles interact with each other
+//! with gravity and charge forces (and other pairwise laws, see `sdl2_test::force`).
+//! The simulation itself lives in the library crate (`sdl2_test::World`),
+//! this binary only emits, draws and navigates it.
+//!
+//! cargo build && cargo run [scene.toml] [--resume snapshot.toml]
+//! cargo run -- [scene.toml] --headless --steps N --out run.csv|run.bin [--particles N]
+//! cargo run -- [scene.toml] --record out.gif|frames/shot [--record-every N] [--record-secs S]
+//! cargo run -- [scene.toml] --trail N
+//! cargo run -- [scene.toml] --seed N
+//! cargo run -- [scene.toml] --adaptive
+//! cargo run -- [scene.toml] --box W H
 
 extern crate sdl2;
-extern crate rand;
+extern crate sdl2_test;
 
+use std::time::Instant;
 
 use sdl2::pixels::PixelFormatEnum;
-use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
+use sdl2::rect::{Rect, Point};
+use sdl2::pixels::Color;
 use sdl2::keyboard::Keycode;
-//use std::ops::Rem;
-//use std::num;
-//use std::sync::mpsc;
-use std::thread;
-//use std::time;
-use std::sync::Arc;
-//use std::sync::Mutex;
-//use sdl2::video::GLProfile;
-use rand::Rng;
-use sdl2::event::Event;
-//use std::cmp;
-
-
-#[derive(Copy, Clone)]
-struct Node {
-    m:  f32, // mass
-    c:  f32, // charge per mass unit
-    px: f32, py: f32, // position
-    vx: f32, vy: f32, // velocity
-    ax: f32, ay: f32, // acceleration
-    fx: f32, fy: f32  // force
+use sdl2::mouse::Mouse;
+use sdl2::event::{Event, WindowEventId};
+use sdl2::video::FullscreenType;
+
+use sdl2_test::{Node, World, Scene, Snapshot, Solver, Integrator, Collisions, Boundary, Diagnostics, DriftLog,
+                TrajectoryWriter, Recorder, RecordOptions, Trails, Colormap, Field, init_nodes_vec,
+                AdaptiveDt, Magnetic, random_seed, fit_step};
+use sdl2_test::{font, sprite, ForceLaw};
+
+
+/// Command line: `[scene.toml] [--resume snapshot.toml] [--headless --steps N --out file] [--particles N]
+/// [--record file --record-every N --record-secs S] [--trail N] [--seed N] [--adaptive] [--box W H]`.
+struct Args {
+    scene:     Option<String>,
+    resume:    Option<String>,
+    headless:  bool,
+    steps:     u64,
+    out:       Option<String>,
+    particles: u32,  // extra particles from `init_nodes_vec`
+    record:    Option<String>, // recording from the first frame
+    record_options: RecordOptions,
+    trail:     Option<usize>, // trail length, trails shown from the start when given
+    seed:      Option<u64>,   // random seed, a fresh one (printed) when not given
+    adaptive:  bool,          // adaptive dt with default criteria, at most the scene's dt
+    box_size:  Option<(f32, f32)>, // world box, replacing the scene's
 }
 
-impl Node {
-    fn get_position_tuple_f32(&self) -> (f32, f32) { (self.px, self.py) }
-    
-    fn get_diameter_f32(&self) -> f32 {
-        //(1.0+(self.m - 10.0)/10.0)
-        1.0
+impl Args {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Args {
+        let mut parsed = Args { scene: None, resume: None, headless: false, steps: 1000, out: None, particles: 0,
+                                record: None, record_options: RecordOptions::default(), trail: None, seed: None,
+                                adaptive: false, box_size: None };
+        let value = |args: &mut I, flag: &str| args.next().unwrap_or_else(|| panic!("{} needs a value", flag));
+        while let Some(a) = args.next() {
+            match a.as_str() {
+                "--resume"    => parsed.resume    = Some(value(&mut args, "--resume")),
+                "--headless"  => parsed.headless  = true,
+                "--adaptive"  => parsed.adaptive  = true,
+                "--steps"     => parsed.steps     = value(&mut args, "--steps").parse().expect("--steps needs a number"),
+                "--out"       => parsed.out       = Some(value(&mut args, "--out")),
+                "--particles" => parsed.particles = value(&mut args, "--particles").parse().expect("--particles needs a number"),
+                "--record"    => parsed.record    = Some(value(&mut args, "--record")),
+                "--record-every" => parsed.record_options.every =
+                    value(&mut args, "--record-every").parse().expect("--record-every needs a number"),
+                "--trail"     => parsed.trail     =
+                    Some(value(&mut args, "--trail").parse().expect("--trail needs a number")),
+                "--seed"      => parsed.seed      =
+                    Some(value(&mut args, "--seed").parse().expect("--seed needs a number")),
+                "--box"       => parsed.box_size  =
+                    Some((value(&mut args, "--box").parse().expect("--box needs a width"),
+                          value(&mut args, "--box").parse().expect("--box needs a height"))),
+                "--record-secs"  => parsed.record_options.max_secs =
+                    Some(value(&mut args, "--record-secs").parse().expect("--record-secs needs a number")),
+                _             => parsed.scene     = Some(a),
+            }
+        }
+        parsed
     }
-    
-    fn draw(&self, 
-            renderer: &mut sdl2::render::Renderer, 
-            textup: (&sdl2::render::Texture, &sdl2::render::Texture),
-            canvasscale:   f32,
-            canvaspan:    (f32, f32),
-            screencenter: (f32, f32)) {
-        // size
-        let diam = self.get_diameter_f32() as u32;
-        
-        // position
-        let post = self.get_position_tuple_f32();
-        // mapping from canvas pos to screen pos
-        let (posx, posy) = ((post.0 + canvaspan.0)*canvasscale + screencenter.0, 
-                            (post.1 + canvaspan.1)*canvasscale + screencenter.1);
-        
-        // texture
-        let tex = { if self.c >= 0.0 { &textup.0 } else { &textup.1 } };
+}
 
-        // actual rendering
-        match renderer.copy(tex, None, Some(Rect::new(posx as i32, posy as i32, diam, diam) ) ) {
-            Result::Ok(val) => val, 
-            Result::Err(err) => panic!("rnd.copy() not ok!: {:?}", err),
-        }
+
+/// Steps the world without SDL, writing every frame (the initial one included) to `out`.
+/// With adaptive dt every step is one frame, of its own length.
+fn run_headless(world: &mut World, dt: f32, steps: u64, out: &str) {
+    let mut traj = TrajectoryWriter::create(out).unwrap_or_else(|e| panic!("{}: {}", out, e));
+    let write = |traj: &mut TrajectoryWriter<_>, world: &World| {
+        traj.write_frame(world.steps(), world.time(), &world.nodes()).unwrap_or_else(|e| panic!("{}: {}", out, e));
+    };
+
+    write(&mut traj, world);
+    for _ in 0..steps {
+        let h = world.next_dt(dt);
+        world.step(h);
+        write(&mut traj, world);
     }
+
+    println!("{}: {} frames ({:?}), {} particles at step {}",
+             out, traj.frames(), traj.format(), world.len(), world.steps());
+    traj.finish().unwrap_or_else(|e| panic!("{}: {}", out, e));
 }
 
 
-fn emit_node(v: &mut Vec<Node>, x: f32, y:f32, vx:f32, vy:f32, m: f32, c: f32) {
-    let node = Node {m: m, c: c, px: x, py: y, vx: vx, vy: vy, ax: 0.0, ay: 0.0, fx: 0.0, fy: 0.0, };
-    v.push(node);
+/// Mapping from canvas pos to screen pos.
+fn canvas_to_screen(pos: (f32, f32), canvasscale: f32, canvaspan: (f32, f32), screencenter: (f32, f32)) -> (f32, f32) {
+    ((pos.0 + canvaspan.0)*canvasscale + screencenter.0,
+     (pos.1 + canvaspan.1)*canvasscale + screencenter.1)
 }
 
 
-fn init_nodes_vec(v: &mut Vec<Node>, n: u32) {
-    let sqrn2 = (n as f32/2.0).sqrt() as f32;
-    //let thresholdn = n/2;
-    let centery = 0.0;
-    let centerx = 0.0;
-    let radius =  200.0;
-    
-    // init random number generator
-    let mut rng = rand::thread_rng();
-    let sp = 1.0;
-    
-    for i in 0..n/2 {
-        let x: f32 = ((i as f32 % sqrn2) + rng.gen::<f32>())*sp;
-        let y: f32 = ((i as f32 / sqrn2) + rng.gen::<f32>())*sp;
-        
-        let node = Node {m: 10.0, c: 5.0, px: centerx - x, py: centery - y - radius , vx: -32.0 + rng.gen::<f32>()/4.0, vy: 3.0, ax: 0.0, ay: 0.0, fx: 0.0, fy: 0.0, };
-        v.push(node);
+/// Screen pos of the middle of a screen of `shape`.
+fn center_of(shape: (u32, u32)) -> (f32, f32) {
+    (0.5*shape.0 as f32, 0.5*shape.1 as f32)
+}
+
+
+/// Mapping from screen pos back to canvas pos.
+fn screen_to_canvas(pos: (f32, f32), canvasscale: f32, canvaspan: (f32, f32), screencenter: (f32, f32)) -> (f32, f32) {
+    ((pos.0 - screencenter.0)/canvasscale - canvaspan.0,
+     (pos.1 - screencenter.1)/canvasscale - canvaspan.1)
+}
+
+
+/// Outline of the world box, if the boundary has one.
+fn draw_boundary(renderer: &mut sdl2::render::Renderer,
+                 boundary: Boundary,
+                 canvasscale:   f32,
+                 canvaspan:    (f32, f32),
+                 screencenter: (f32, f32)) {
+    if let Some((w, h)) = boundary.size() {
+        let corners = [(-0.5*w, -0.5*h), (0.5*w, -0.5*h), (0.5*w, 0.5*h), (-0.5*w, 0.5*h)];
+        let pts: Vec<Point> = corners.iter().chain(corners.iter().take(1)).map(|&c| {
+            let p = canvas_to_screen(c, canvasscale, canvaspan, screencenter);
+            Point::new(p.0 as i32, p.1 as i32)
+        }).collect();
+
+        renderer.set_draw_color(Color::RGB(96, 96, 96));
+        renderer.draw_lines(&pts[..]).unwrap();
+        renderer.set_draw_color(Color::RGB(0, 0, 0));
     }
-    
-    for i in 0..n/2 {
-        let x: f32 = ((i as f32 % sqrn2) + rng.gen::<f32>())*sp;
-        let y: f32 = ((i as f32 / sqrn2) + rng.gen::<f32>())*sp;
+}
+
+
+/// Substeps per frame and dt of each, for a rate of `2^rate_exp` steps of `dt` per frame.
+/// Faster rates add substeps (same dt, same accuracy), slower ones shrink dt.
+fn frame_steps(dt: f32, rate_exp: i32) -> (u32, f32) {
+    (1 << rate_exp.max(0), dt*0.5f32.powi((-rate_exp).max(0)))
+}
+
 
-        let node = Node {m: 10.0, c: -5.0, px: centerx + x, py: centery + y + radius, vx: 32.0 - rng.gen::<f32>()/4.0, vy: -3.0, ax: 0.0, ay: 0.0, fx: 0.0, fy: 0.0, };
-        v.push(node);
+/// Window title showing the current simulation rate.
+fn title(paused: bool, dt: f32, rate_exp: i32) -> String {
+    let (substeps, step_dt) = frame_steps(dt, rate_exp);
+    let rate = 2.0f32.powi(rate_exp);
+    if paused {
+        format!("Rust on SDL2 - paused (x{}, {} x dt {})", rate, substeps, step_dt)
+    } else {
+        format!("Rust on SDL2 - x{} ({} x dt {})", rate, substeps, step_dt)
     }
 }
 
 
-// computing forces, velocities, positions
-fn update_nodes_vec(v: &mut Vec<Node>, dt: f32) {
-    let vec_a = Arc::new(v.to_vec());
-    //let vec_a = v.to_vec();
-    let mut threadsv = Vec::with_capacity(v.len());
-        
-    for i in 0..v.len() {
-        let n_c = (&v[i]).clone();
-        let vec_ac = vec_a.clone();
-        
-        let child = thread::spawn(move || {            
-            //println!("T{}...", i);
-            
-            // delay
-            //if i == 1 {thread::sleep(time::Duration::from_millis(100))};
-        
-            let mut fv = (0.0, 0.0); 
-        
-            //for j in 0..vec_ac.len() {  // 13% core::iter::range iterator 
-            //for m in vec_ac.iter().by_ref() {  // 40% core slice iter
-            for m in vec_ac.iter() {  // FIXME: 40% core slice iter - main bottleneck 
-            
-                //if i == j { continue; }
-                //assert!(i != j);
-            
-                let n = &n_c;       // from node
-                if n.px == m.px && n.py == m.py { continue; }
-                //let m = &vec_ac[j]; // to node //6% alloc..arc..Arc; 16% collections..vec..Vec core ops index
-                
-                let dthr = 4.0;                
-            
-                let dnm  = (m.px - n.px, m.py - n.py);                  // distance vector
-                //let d    = ((dnm.0).powi(2) + (dnm.1).powi(2) ).sqrt(); // distance scalar // 14% powi
-                let mut d    = (dnm.0*dnm.0 + dnm.1*dnm.1).sqrt(); // distance scalar
-                if d < dthr {d = dthr;}
-                let dirv = (dnm.0/d, dnm.1/d);                          // direction vector
-                
-                //let fg   = 10.0*n.m*m.m/(d.powi(2));     // gravity force scalar  // 7% powi
-
-                let fg = 10.0*n.m*m.m/(d*d);     // gravity force scalar
-                let fgnm = (fg*dirv.0, fg*dirv.1); // gravity force vector
-                
-                //let fc   = -10.0*n.c*m.c/(d.powi(2));    // coulomb force scalar  // 7% powi
-                let fc   = -10.0*n.c*m.c/(d*d);    // coulomb force scalar
-                let fcnm = (fc*dirv.0, fc*dirv.1); // coulomb force vector
-                
-                fv.0 += fgnm.0 + fcnm.0;  // result force vector - x
-                fv.1 += fgnm.1 + fcnm.1;  // result force vector - y
-            }
-            
-            //println!("T{} done", i);
-        
-            (fv.0, fv.1) // force returned
-        });
-        
-        // push thread to vector
-        &threadsv.push(child);
+/// Trails as line segments fading out towards their oldest end,
+/// orange or blue like the nodes.
+fn draw_trails(renderer: &mut sdl2::render::Renderer,
+               trails: &Trails,
+               nodes: &[Node],
+               canvasscale:   f32,
+               canvaspan:    (f32, f32),
+               screencenter: (f32, f32)) {
+    let max = trails.max_len().max(2) as f32;
+    for n in nodes {
+        let pts = match trails.get(n.id) { Some(p) => p, None => continue };
+        let base = if n.c >= 0.0 { (255.0, 128.0, 50.0) } else { (50.0, 128.0, 255.0) };
+
+        // newest segments are drawn brightest
+        let skip = max as usize - pts.len().min(max as usize);
+        for (k, (a, b)) in pts.iter().zip(pts.iter().skip(1)).enumerate() {
+            let f = 0.6*(skip + k + 1) as f32/max;
+            renderer.set_draw_color(Color::RGB((base.0*f) as u8, (base.1*f) as u8, (base.2*f) as u8));
+
+            let a = canvas_to_screen(*a, canvasscale, canvaspan, screencenter);
+            let b = canvas_to_screen(*b, canvasscale, canvaspan, screencenter);
+            renderer.draw_line(Point::new(a.0 as i32, a.1 as i32), Point::new(b.0 as i32, b.1 as i32)).unwrap();
+        }
     }
-    
-    let th_ret: Vec<(f32, f32)> = threadsv.into_iter().map(|t| t.join().unwrap()).collect();
-    //println!(" >: {:?}", th_ret);
-    
-    for i in 0..v.len() {
-        let mut n = &mut v[i];
-        let  fv   = &th_ret[i];
-        n.fx = fv.0;
-        n.fy = fv.1;
-        
-        let av = (fv.0/n.m, fv.1/n.m);
-        n.ax = av.0;
-        n.ay = av.1;
-        
-        //let kv = 1.0 - 0.001*dt;  // drag
-        let kv = 1.0;  // drag
-        //let kv = 1.0;  // drag
+    renderer.set_draw_color(Color::RGB(0, 0, 0));
+}
+
+
+/// Gradient bar of `cmap` with the field name and its range.
+fn draw_legend(renderer: &mut sdl2::render::Renderer, cmap: Colormap, field: Field, range: (f32, f32), pos: (i32, i32)) {
+    let (w, h) = (128, 8);
+    for x in 0..w {
+        let c = cmap.color(x as f32/(w - 1) as f32);
+        renderer.set_draw_color(Color::RGB(c.0, c.1, c.2));
+        renderer.fill_rect(Rect::new(pos.0 + x, pos.1, 1, h)).unwrap();
+    }
+
+    let label = format!("{:?} {:.3} .. {:.3}", field, range.0, range.1);
+    draw_text(renderer, &label, (pos.0 + w + 8, pos.1), 1, Color::RGB(200, 200, 200));
+}
+
+
+/// Yellow frame around the selected node.
+fn draw_selection(renderer: &mut sdl2::render::Renderer,
+                  node: &Node,
+                  canvasscale:   f32,
+                  canvaspan:    (f32, f32),
+                  screencenter: (f32, f32),
+                  zoomsize:      bool) {
+    let diam = node.get_diameter_f32()*if zoomsize { canvasscale } else { 1.0 };
+    let side = diam.round().max(1.0) as i32 + 8;
+    let p = canvas_to_screen(node.get_position_tuple_f32(), canvasscale, canvaspan, screencenter);
+
+    renderer.set_draw_color(Color::RGB(255, 230, 60));
+    renderer.draw_rect(Rect::new(p.0 as i32 - side/2, p.1 as i32 - side/2, side as u32, side as u32)).unwrap();
+    renderer.set_draw_color(Color::RGB(0, 0, 0));
+}
+
+
+/// Text in the built-in bitmap font, `scale` screen pixels per font pixel.
+fn draw_text(renderer: &mut sdl2::render::Renderer, text: &str, pos: (i32, i32), scale: i32, color: Color) {
+    let mut rects = Vec::new();
+    font::for_each_pixel(text, |x, y| {
+        rects.push(Rect::new(pos.0 + x*scale, pos.1 + y*scale, scale as u32, scale as u32));
+    });
+
+    renderer.set_draw_color(color);
+    renderer.fill_rects(&rects[..]).unwrap();
+    renderer.set_draw_color(Color::RGB(0, 0, 0));
+}
+
+
+/// White round sprites of `sprite::SPRITE_SIZES`, tinted per node.
+struct Sprites {
+    textures:   Vec<(u32, sdl2::render::Texture)>,
+    zoom_sizes: bool, // sizes follow the zoom, or stay in screen pixels
+}
+
+
+/// Rendering of simulation nodes on the SDL2 canvas.
+trait Draw {
+    fn draw(&self,
+            renderer: &mut sdl2::render::Renderer,
+            sprites: &mut Sprites,
+            color: (u8, u8, u8),
+            canvasscale:   f32,
+            canvaspan:    (f32, f32),
+            screencenter: (f32, f32));
+}
+
+impl Draw for Node {
+    fn draw(&self, 
+            renderer: &mut sdl2::render::Renderer, 
+            sprites: &mut Sprites,
+            color: (u8, u8, u8),
+            canvasscale:   f32,
+            canvaspan:    (f32, f32),
+            screencenter: (f32, f32)) {
+        // size, from mass and optionally zoom
+        let diam = self.get_diameter_f32()*if sprites.zoom_sizes { canvasscale } else { 1.0 };
+        let side = diam.round().max(1.0) as u32;
         
-        let mut vv = (n.vx + av.0*dt, n.vy + av.1*dt);
-        vv.0 *= kv;
-        vv.1 *= kv;
-        n.vx = vv.0;
-        n.vy = vv.1;
+        // position
+        let post = self.get_position_tuple_f32();
+        // mapping from canvas pos to screen pos
+        let (posx, posy) = canvas_to_screen(post, canvasscale, canvaspan, screencenter);
         
-        let pv = (n.px + vv.0*dt, n.py + vv.1*dt);
-        n.px = pv.0;
-        n.py = pv.1;
+        // smallest sprite covering the diameter, tinted
+        let size = sprite::sprite_size(diam);
+        let tex = &mut sprites.textures.iter_mut().find(|s| s.0 == size).unwrap().1;
+        tex.set_color_mod(color.0, color.1, color.2);
+
+        // actual rendering, centered at the node
+        let half = (side/2) as i32;
+        match renderer.copy(tex, None, Some(Rect::new(posx as i32 - half, posy as i32 - half, side, side) ) ) {
+            Result::Ok(val) => val, 
+            Result::Err(err) => panic!("rnd.copy() not ok!: {:?}", err),
+        }
     }
-    
 }
 
 
 fn main() {
-    let     screen_shape_tup:    (u32, u32) = (640, 480); // screen dimensions (x,y)
+    let mut screen_shape_tup:    (u32, u32) = (640, 480); // screen dimensions (x,y), follows the window
     let mut canvas_pan_tup:      (f32, f32) = (0.0, 0.0); // translation of canvas coords
     let mut canvas_dynamics_tup: (f32, f32, f32) = (0.0, 0.0, 1.0); // speed of dynamics change (vpanx, vpany, vzoom)
     let mut canvas_zoom:          f32       = 1.0;        // zoom of canvas surface points from (0,0)
+    let mut screen_center:       (f32, f32) = center_of(screen_shape_tup); // screen pos of canvas (0,0) at no pan
+    let mut mouse_drag: Option<(Mouse, i32)> = None;      // held button and distance dragged so far
+    let mut mouse_pos:           (f32, f32) = screen_center; // last known cursor pos, anchors the wheel zoom
     
-    let tex_res: u32 = 1;  
     
-    let n = 2048;
-    let mut vecnodes: Vec<Node> = Vec::new();
+    let args = Args::parse(std::env::args().skip(1));
+
+    // scene from the command line, or the built-in one
+    let scene = match args.scene {
+        Some(ref path) => Scene::load(path).unwrap_or_else(|e| panic!("{}: {}", path, e)),
+        None           => Scene::parse(sdl2_test::scene::DEFAULT_SCENE).unwrap(),
+    };
+
+    // one seed for all initialisation and emission, so runs can be repeated
+    let seed = args.seed.unwrap_or_else(random_seed);
+
+    // a snapshot replaces the scene's initial state, step and parameters
+    let (mut world, dt) = match args.resume {
+        Some(ref path) => {
+            // the snapshot is the whole state, extra particles would change it
+            assert!(args.particles == 0, "--particles cannot be used with --resume");
+            let snap = Snapshot::load(path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+            println!("resumed {} at step {} ({} particles)", path, snap.steps, snap.nodes.len());
+            // a snapshot carries the seed of its run, so emission goes on the same way
+            let mut world = snap.restore();
+            if snap.seed.is_none() { world.set_seed(seed); }
+            (world, snap.dt)
+        },
+        None => (scene.build_seeded(seed), scene.dt),
+    };
+    println!("seed: {}", world.seed());
+    let mut extra = Vec::new();
+    init_nodes_vec(&mut extra, args.particles, world.rng_mut());
+    world.add_nodes(extra);
+    if args.adaptive && world.adaptive().is_none() {
+        // the default min_dt may be above a small scene dt
+        let a = AdaptiveDt::default();
+        world.set_adaptive(Some(AdaptiveDt { max_dt: dt, min_dt: a.min_dt.min(dt), ..a }));
+    }
+    if let Some(a) = world.adaptive() { println!("adaptive dt: {:?}", a); }
+
+    // world box for reflective/periodic boundary: --box, the scene's or snapshot's, or 800x600
+    let box_size = args.box_size.or_else(|| world.boundary().size()).unwrap_or((800.0, 600.0));
+    let boundary = match world.boundary() {
+        Boundary::Open              => Boundary::Open,
+        Boundary::Reflective { .. } => Boundary::Reflective { w: box_size.0, h: box_size.1 },
+        Boundary::Periodic   { .. } => Boundary::Periodic   { w: box_size.0, h: box_size.1 },
+    };
+    world.set_boundary(boundary);
+
+    if args.headless {
+        let out = args.out.as_ref().expect("--headless needs --out file");
+        run_headless(&mut world, dt, args.steps, out);
+        return;
+    }
 
     let mut run = true;
     
-    // frame counter
-    let mut nframes: u64 = 0;
-    
-    
-    let mut rng = rand::thread_rng();
+    // conserved quantities, logged every `diag_every` steps when enabled
+    let mut diag_log: Option<DriftLog> = None;
+    let diag_every: u64 = 100;
+
+    // coloring by a node field (V) with a colormap (K), or by charge sign when off
+    let mut color_field: Option<Field> = None;
+    let mut colormap = Colormap::default();
+
+    // last field switched off with M, restored by the next press (uniform 0.1 if there was none)
+    let mut magnetic: Option<Magnetic> = None;
+
+    // trails of recent positions, toggled with T
+    let mut trails: Option<Trails> = args.trail.map(Trails::new);
+    let trail_len = args.trail.unwrap_or(32);
+
+    // pausing, single steps and rate (steps of `dt` per frame, as a power of two)
+    let mut paused = false;
+    let mut step_once = false;
+    let mut rate_exp: i32 = 0;
+
+    // id of the node picked with the middle button, optionally followed (F)
+    let mut selected: Option<u64> = None;
+    let mut follow = false;
+
+    // overlay with frame rate and simulation stats, toggled with H
+    let mut show_hud = true;
+    let mut fps_clock = Instant::now();
+    let mut fps_frames: u32 = 0;
+    let mut fps: f32 = 0.0;
+    let mut step_ms: f32 = 0.0;
+    let mut frame_dt: f32 = dt;         // smallest step of the last frame, varies with adaptive dt
+    let mut energy: Option<f64> = None; // last measured, only while diagnostics are on
+
+    // recording of presented frames, toggled with R
+    let mut recorder = args.record.as_ref().map(|path| Recorder::new(path, args.record_options));
    
     let sdl_ctx = sdl2::init().unwrap();
     let sdl_ctx_vid = sdl_ctx.video().unwrap();
     let gl_attr = sdl_ctx_vid.gl_attr();
 
     // window object
-    let win = sdl_ctx_vid.window(&"Rust on SDL2", screen_shape_tup.0, screen_shape_tup.1)
+    let win = sdl_ctx_vid.window(&title(paused, dt, rate_exp), screen_shape_tup.0, screen_shape_tup.1)
         .position_centered()
+        .resizable()
         .opengl()
         .build()
         .unwrap();
@@ -240,58 +405,72 @@ fn main() {
     gl_attr.set_multisample_buffers(1);
     gl_attr.set_multisample_samples(4);
 
-    // orange texture
-    let mut texturerg = rnd.create_texture_streaming(PixelFormatEnum::RGB24, tex_res, tex_res).unwrap();
-    texturerg.with_lock(None, |buffer: &mut [u8], p: usize| {
-        for y in 0..tex_res {
-            for x in 0..tex_res {
-                let t: usize = (y*p as u32 + x*3) as usize;
-                buffer[t + 0] = 255;
-                buffer[t + 1] = 128;
-                buffer[t + 2] = 50;
-            }
-        }
-    }).unwrap();
-    
-    // blue texture
-    let mut texturegb = rnd.create_texture_streaming(PixelFormatEnum::RGB24, tex_res, tex_res).unwrap();
-    texturegb.with_lock(None, |buffer: &mut [u8], p: usize| {
-        for y in 0..tex_res {
-            for x in 0..tex_res {
-                let t: usize = (y*p as u32 + x*3) as usize;
-                buffer[t + 0] = 50;
-                buffer[t + 1] = 128;
-                buffer[t + 2] = 255;
-            }
-        }
-    }).unwrap();
-    
-    // generate nodes
-    //init_nodes_vec(&mut vecnodes, n as u32 /2);
-      
+    // round sprites of a few sizes, built once
+    let mut sprites = Sprites {
+        textures: sprite::SPRITE_SIZES.iter().map(|&size| {
+            // ABGR8888 is R, G, B, A in memory on little-endian machines
+            let mut tex = rnd.create_texture_static(PixelFormatEnum::ABGR8888, size, size).unwrap();
+            tex.update(None, &sprite::disc_rgba(size), (size*4) as usize).unwrap();
+            tex.set_blend_mode(BlendMode::Blend);
+            (size, tex)
+        }).collect(),
+        zoom_sizes: false,
+    };
+
     // main loop
     while run {
         rnd.clear(); // clearing window
         
-        // emiting new particles
-        let vnum = vecnodes.len();
-        if vnum < n {
-            let em0 = ( (-200) as f32 + rng.gen::<f32>(), (32) as f32 + rng.gen::<f32>() );
-            let em1 = ( (200) as f32 + rng.gen::<f32>(), (-32) as f32 + rng.gen::<f32>() );
-        
-            if nframes % 1 == 0 {
-                emit_node(&mut vecnodes, em0.0, em0.1,  
-                    10.0, 10.0, 
-                    20.0, -10.0);
-                emit_node(&mut vecnodes, em1.0, em1.1,  
-                    -10.0, -10.0, 
-                    20.0, 10.0);
+        // drawing world box, trails and particles
+        let nodes = world.nodes();
+        draw_boundary(&mut rnd, world.boundary(), canvas_zoom, canvas_pan_tup, screen_center);
+        if let Some(ref t) = trails {
+            draw_trails(&mut rnd, t, &nodes, canvas_zoom, canvas_pan_tup, screen_center);
+        }
+        let range = color_field.map(|f| f.range(&nodes));
+        for n in &nodes {
+            let color = match (color_field, range) {
+                (Some(field), Some(range)) => colormap.color(field.normalize(n, range)),
+                _ => if n.c >= 0.0 { (255, 128, 50) } else { (50, 128, 255) }, // orange or blue
+            };
+            n.draw(&mut rnd, &mut sprites, color, canvas_zoom, canvas_pan_tup, screen_center);
+        }
+        if let (Some(field), Some(range)) = (color_field, range) {
+            draw_legend(&mut rnd, colormap, field, range, (8, screen_shape_tup.1 as i32 - 16));
+        }
+
+        if let Some(n) = selected.and_then(|id| world.index_of(id)).map(|i| &nodes[i]) {
+            draw_selection(&mut rnd, n, canvas_zoom, canvas_pan_tup, screen_center, sprites.zoom_sizes);
+
+            let text = format!("NODE {}{}\nM {:.3}  C {:.3}\nPOS {:.2} {:.2}\nVEL {:.3} {:.3}\nACC {:.3} {:.3}\nFORCE {:.3} {:.3}",
+                               n.id, if follow { "  (FOLLOW)" } else { "" }, n.m, n.c,
+                               n.px, n.py, n.vx, n.vy, n.ax, n.ay, n.fx, n.fy);
+            let w = font::text_size(&text).0;
+            draw_text(&mut rnd, &text, (screen_shape_tup.0 as i32 - w - 8, 8), 1, Color::RGB(255, 230, 60));
+        }
+
+        if show_hud {
+            let mut hud = format!("FPS {:.1}  STEP {:.2} MS\nN {}  T {:.2}  ({} STEPS)\nDT {:.2e}{}\nZOOM {:.3}  PAN {:.0} {:.0}",
+                                  fps, step_ms, world.len(), world.time(), world.steps(),
+                                  frame_dt, if world.adaptive().is_some() { " (ADAPTIVE, MIN)" } else { "" },
+                                  canvas_zoom, canvas_pan_tup.0, canvas_pan_tup.1);
+            if let Some(e) = energy { hud.push_str(&format!("\nE {:.5e}", e)); }
+            draw_text(&mut rnd, &hud, (8, 8), 1, Color::RGB(200, 200, 200));
+        }
+
+        // reading back the frame before it is presented
+        // (paused frames are not counted, so recordings follow the simulation)
+        if let Some(ref mut rec) = recorder {
+            if !paused && rec.tick() {
+                let (w, h) = rnd.output_size().unwrap();
+                let rgb = rnd.read_pixels(None, PixelFormatEnum::RGB24).unwrap();
+                rec.add_frame(w, h, &rgb).unwrap_or_else(|e| panic!("{}: {}", rec.path().display(), e));
             }
         }
-        
-        // drawing particles
-        for n in &vecnodes {
-            n.draw(&mut rnd, (&texturerg, &texturegb), canvas_zoom, canvas_pan_tup, (320.0, 240.0));
+        if recorder.as_ref().is_some_and(|r| r.is_done()) {
+            let rec = recorder.take().unwrap();
+            println!("recorded {} ({} frames, max duration reached)", rec.path().display(), rec.frames());
+            rec.finish();
         }
 
         rnd.present(); // rendering
@@ -300,12 +479,184 @@ fn main() {
         for event in sdl_ctx.event_pump().unwrap().poll_iter() {
             match event {
                 Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => { run = false },
+                Event::Window { win_event_id: WindowEventId::SizeChanged, data1, data2, .. } => {
+                    // the canvas origin stays in the middle of the window
+                    screen_shape_tup = (data1.max(1) as u32, data2.max(1) as u32);
+                    screen_center = center_of(screen_shape_tup);
+                    rnd.set_viewport(Some(Rect::new(0, 0, screen_shape_tup.0, screen_shape_tup.1)));
+                },
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => {
+                    let win = rnd.window_mut().unwrap();
+                    let fullscreen = match win.fullscreen_state() {
+                        FullscreenType::Off => FullscreenType::Desktop,
+                        _                   => FullscreenType::Off,
+                    };
+                    win.set_fullscreen(fullscreen).unwrap();
+                },
                 Event::KeyDown { keycode: Some(Keycode::D), .. } => { canvas_dynamics_tup.0 =-10.0/canvas_zoom },
                 Event::KeyDown { keycode: Some(Keycode::A), .. } => { canvas_dynamics_tup.0 = 10.0/canvas_zoom },
                 Event::KeyDown { keycode: Some(Keycode::S), .. } => { canvas_dynamics_tup.1 =-10.0/canvas_zoom },
                 Event::KeyDown { keycode: Some(Keycode::W), .. } => { canvas_dynamics_tup.1 = 10.0/canvas_zoom },
                 Event::KeyDown { keycode: Some(Keycode::KpPlus), .. } => { canvas_dynamics_tup.2 = 1.05 },
                 Event::KeyDown { keycode: Some(Keycode::KpMinus), .. } => { canvas_dynamics_tup.2 = 0.95 },
+                Event::KeyDown { keycode: Some(Keycode::B), repeat: false, .. } => {
+                    let solver = match world.solver() {
+                        Solver::Exact => Solver::BarnesHut { theta: 0.5 },
+                        // the cell list only for short-range laws, it would cut long-range ones off
+                        Solver::BarnesHut { .. } => match world.laws().cutoff() {
+                            Some(cutoff) => Solver::CellList { cutoff },
+                            None         => Solver::Exact,
+                        },
+                        Solver::CellList { .. } => Solver::Exact,
+                    };
+                    println!("solver: {:?}", solver);
+                    world.set_solver(solver);
+                },
+                Event::KeyDown { keycode: Some(Keycode::I), repeat: false, .. } => {
+                    let integrator = match world.integrator() {
+                        Integrator::Euler             => Integrator::SemiImplicitEuler,
+                        Integrator::SemiImplicitEuler => Integrator::VelocityVerlet,
+                        Integrator::VelocityVerlet    => Integrator::Rk4,
+                        Integrator::Rk4               => Integrator::Boris,
+                        Integrator::Boris             => Integrator::Euler,
+                    };
+                    println!("integrator: {:?}", integrator);
+                    world.set_integrator(integrator);
+                },
+                Event::KeyDown { keycode: Some(Keycode::M), repeat: false, .. } => {
+                    match world.magnetic() {
+                        Some(b) => { world.set_magnetic(None); magnetic = Some(b); },
+                        None    => world.set_magnetic(Some(magnetic.unwrap_or(Magnetic::Uniform { bz: 2.0 }))),
+                    }
+                    println!("magnetic field: {:?}", world.magnetic());
+                },
+                Event::KeyDown { keycode: Some(Keycode::C), repeat: false, .. } => {
+                    let collisions = match world.collisions() {
+                        Collisions::Off     => Collisions::Merge,
+                        Collisions::Merge   => Collisions::Elastic,
+                        Collisions::Elastic => Collisions::Off,
+                    };
+                    println!("collisions: {:?}", collisions);
+                    world.set_collisions(collisions);
+                },
+                Event::KeyDown { keycode: Some(Keycode::X), repeat: false, .. } => {
+                    let boundary = match world.boundary() {
+                        Boundary::Open              => Boundary::Reflective { w: box_size.0, h: box_size.1 },
+                        Boundary::Reflective { .. } => Boundary::Periodic   { w: box_size.0, h: box_size.1 },
+                        Boundary::Periodic   { .. } => Boundary::Open,
+                    };
+                    println!("boundary: {:?}", boundary);
+                    world.set_boundary(boundary);
+                },
+                Event::KeyDown { keycode: Some(Keycode::E), repeat: false, .. } => {
+                    diag_log = match diag_log { Some(_) => None, None => Some(DriftLog::new()) };
+                    energy = None;
+                    println!("diagnostics: {}", if diag_log.is_some() { "on" } else { "off" });
+                },
+                Event::KeyDown { keycode: Some(Keycode::H), repeat: false, .. } => { show_hud = !show_hud },
+                Event::KeyDown { keycode: Some(Keycode::V), repeat: false, .. } => {
+                    color_field = match color_field {
+                        None                      => Some(Field::Speed),
+                        Some(Field::Speed)        => Some(Field::Force),
+                        Some(Field::Force)        => Some(Field::Acceleration),
+                        Some(Field::Acceleration) => Some(Field::Mass),
+                        Some(Field::Mass)         => Some(Field::Charge),
+                        Some(Field::Charge)       => None,
+                    };
+                    println!("color field: {:?}", color_field);
+                },
+                Event::KeyDown { keycode: Some(Keycode::K), repeat: false, .. } => {
+                    colormap = match colormap {
+                        Colormap::Viridis   => Colormap::Heat,
+                        Colormap::Heat      => Colormap::Diverging,
+                        Colormap::Diverging => Colormap::Viridis,
+                    };
+                    println!("colormap: {:?}", colormap);
+                },
+                Event::KeyDown { keycode: Some(Keycode::Z), repeat: false, .. } => {
+                    sprites.zoom_sizes = !sprites.zoom_sizes;
+                    println!("particle size follows zoom: {}", sprites.zoom_sizes);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F), repeat: false, .. } => {
+                    follow = !follow;
+                    println!("follow selected: {}", follow);
+                },
+                Event::KeyDown { keycode: Some(Keycode::T), repeat: false, .. } => {
+                    trails = match trails { Some(_) => None, None => Some(Trails::new(trail_len)) };
+                    println!("trails: {}", if trails.is_some() { "on" } else { "off" });
+                },
+                Event::KeyDown { keycode: Some(Keycode::Space), repeat: false, .. } => {
+                    paused = !paused;
+                    rnd.window_mut().unwrap().set_title(&title(paused, dt, rate_exp)).unwrap();
+                },
+                Event::KeyDown { keycode: Some(Keycode::Period), .. } => { step_once = paused },
+                Event::KeyDown { keycode: Some(Keycode::RightBracket), .. } => {
+                    rate_exp = (rate_exp + 1).min(5);
+                    rnd.window_mut().unwrap().set_title(&title(paused, dt, rate_exp)).unwrap();
+                },
+                Event::KeyDown { keycode: Some(Keycode::LeftBracket), .. } => {
+                    rate_exp = (rate_exp - 1).max(-4);
+                    rnd.window_mut().unwrap().set_title(&title(paused, dt, rate_exp)).unwrap();
+                },
+                Event::KeyDown { keycode: Some(Keycode::R), repeat: false, .. } => {
+                    recorder = match recorder.take() {
+                        Some(rec) => {
+                            println!("recorded {} ({} frames)", rec.path().display(), rec.frames());
+                            rec.finish();
+                            None
+                        },
+                        None => {
+                            let path = args.record.clone().unwrap_or_else(|| format!("recording_{:08}.gif", world.steps()));
+                            println!("recording to {}", path);
+                            Some(Recorder::new(path, args.record_options))
+                        },
+                    };
+                },
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => {
+                    let path = format!("snapshot_{:08}.toml", world.steps());
+                    match Snapshot::capture(&world, dt).save(&path) {
+                        Ok(())   => println!("saved {}", path),
+                        Err(err) => println!("{}: {}", path, err),
+                    }
+                },
+                Event::MouseButtonDown { mouse_btn, .. } => { mouse_drag = Some((mouse_btn, 0)) },
+                Event::MouseMotion { x, y, xrel, yrel, .. } => {
+                    mouse_pos = (x as f32, y as f32);
+
+                    // dragging with any button pans the canvas along with the cursor
+                    if let Some((_, ref mut dragged)) = mouse_drag {
+                        canvas_pan_tup.0 += xrel as f32/canvas_zoom;
+                        canvas_pan_tup.1 += yrel as f32/canvas_zoom;
+                        *dragged += xrel.abs() + yrel.abs();
+                    }
+                },
+                Event::MouseButtonUp { x, y, .. } => {
+                    // a click without dragging: middle selects the node under the cursor,
+                    // left/right spawn a resting positive/negative particle there
+                    let click = match mouse_drag.take() { Some((b, d)) if d < 4 => Some(b), _ => None };
+                    let p = screen_to_canvas((x as f32, y as f32), canvas_zoom, canvas_pan_tup, screen_center);
+                    let charge = match click {
+                        Some(Mouse::Left)  =>  5.0,
+                        Some(Mouse::Right) => -5.0,
+                        _ => 0.0,
+                    };
+
+                    if click == Some(Mouse::Middle) {
+                        selected = world.nearest(p, 8.0/canvas_zoom).map(|i| world.node(i).id);
+                        println!("selected: {:?}", selected);
+                    } else if charge != 0.0 && world.len() < world.max_nodes() {
+                        world.add_node(Node::new(p.0, p.1, 0.0, 0.0, 10.0, charge));
+                    }
+                },
+                Event::MouseWheel { y: wheel, .. } if wheel != 0 => {
+                    // zooming around the cursor: the canvas point under it stays in place
+                    let cursor = mouse_pos;
+                    let anchor = screen_to_canvas(cursor, canvas_zoom, canvas_pan_tup, screen_center);
+
+                    canvas_zoom *= 1.1f32.powi(wheel);
+                    canvas_pan_tup = ((cursor.0 - screen_center.0)/canvas_zoom - anchor.0,
+                                      (cursor.1 - screen_center.1)/canvas_zoom - anchor.1);
+                },
                 Event::KeyUp { keycode: Some(Keycode::D), .. } => { canvas_dynamics_tup.0 = 0.0 },
                 Event::KeyUp { keycode: Some(Keycode::A), .. } => { canvas_dynamics_tup.0 = 0.0 },
                 Event::KeyUp { keycode: Some(Keycode::S), .. } => { canvas_dynamics_tup.1 = 0.0 },
@@ -320,13 +671,61 @@ fn main() {
         canvas_pan_tup.0 += canvas_dynamics_tup.0;
         canvas_pan_tup.1 += canvas_dynamics_tup.1;
         canvas_zoom      *= canvas_dynamics_tup.2;
+
+        // keeping the followed node in the middle of the window
+        if let (true, Some(i)) = (follow, selected.and_then(|id| world.index_of(id))) {
+            let pos = world.node(i).get_position_tuple_f32();
+            canvas_pan_tup = (-pos.0, -pos.1);
+        }
         
-        // updating nodes forces, accel, vel, positions
-        update_nodes_vec(&mut vecnodes, 0.01);
-        
-        // updating frame counter
-        nframes += 1;
-    }
-}
+        // emiting new particles, updating nodes forces, accel, vel, positions
+        if !paused || step_once {
+            let (substeps, step_dt) = frame_steps(dt, rate_exp);
+            let step_start = Instant::now();
 
+            // fixed: `substeps` steps of `step_dt`; adaptive: as many steps as it takes
+            // to cover the same simulated time, the last ones fitted to end on it
+            let (mut taken, mut left) = (0, substeps as f32*step_dt);
+            let adaptive = world.adaptive().is_some();
+            frame_dt = f32::MAX;
+            while if adaptive { left > 0.0 } else { taken < substeps } {
+                let h = if adaptive { fit_step(world.next_dt(step_dt), left) } else { step_dt };
+                world.step(h);
+                taken += 1;
+                left  -= h;
+                frame_dt = frame_dt.min(h);
 
+                // logging energy, momentum and angular momentum drift
+                if let Some(ref mut log) = diag_log {
+                    if world.steps().is_multiple_of(diag_every) {
+                        let d = Diagnostics::measure_with(&world.nodes(), world.boundary(), world.laws());
+                        let drift = log.record(world.time(), d);
+                        println!("{} | {}", d, drift);
+                        energy = Some(d.total_energy());
+                    }
+                }
+            }
+            step_ms = step_start.elapsed().as_secs_f32()*1000.0;
+            step_once = false;
+
+            if let Some(ref mut t) = trails { t.record(&world.nodes(), world.boundary()); }
+
+            // the selection follows its node by id, and ends when the node is merged away
+            if let Some(id) = selected {
+                if world.index_of(id).is_none() {
+                    println!("selected node {} merged away", id);
+                    selected = None;
+                }
+            }
+        }
+
+        // frame rate, averaged over about half a second
+        fps_frames += 1;
+        let fps_elapsed = fps_clock.elapsed().as_secs_f32();
+        if fps_elapsed >= 0.5 {
+            fps = fps_frames as f32/fps_elapsed;
+            fps_frames = 0;
+            fps_clock = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Particle state and helpers for building particle vectors.
+
+use rand::Rng;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Node {
+    #[serde(default)]
+    pub id: u64, // stable identity, given by `World` (0 until then)
+    pub m:  f32, // mass
+    pub c:  f32, // charge, for electrostatics and the Lorentz force alike
+    pub px: f32, pub py: f32, // position
+    pub vx: f32, pub vy: f32, // velocity
+    pub ax: f32, pub ay: f32, // acceleration
+    pub fx: f32, pub fy: f32  // force
+}
+
+impl Node {
+    pub fn new(x: f32, y: f32, vx: f32, vy: f32, m: f32, c: f32) -> Node {
+        Node {id: 0, m, c, px: x, py: y, vx, vy, ax: 0.0, ay: 0.0, fx: 0.0, fy: 0.0, }
+    }
+
+    pub fn get_position_tuple_f32(&self) -> (f32, f32) { (self.px, self.py) }
+
+    pub fn get_velocity_tuple_f32(&self) -> (f32, f32) { (self.vx, self.vy) }
+
+    /// Diameter in canvas units, see `diameter`.
+    pub fn get_diameter_f32(&self) -> f32 { diameter(self.m) }
+}
+
+
+/// Diameter of a node of mass `m`, in canvas units; the area grows with mass, 2.0 at `m = 10`.
+pub fn diameter(m: f32) -> f32 {
+    2.0*(m.max(0.0)/10.0).sqrt()
+}
+
+
+pub fn emit_node(v: &mut Vec<Node>, x: f32, y:f32, vx:f32, vy:f32, m: f32, c: f32) {
+    v.push(Node::new(x, y, vx, vy, m, c));
+}
+
+
+/// Appends two opposite blocks of `n/2` nodes each, jittered with `rng`.
+pub fn init_nodes_vec<R: Rng>(v: &mut Vec<Node>, n: u32, rng: &mut R) {
+    let sqrn2 = (n as f32/2.0).sqrt();
+    //let thresholdn = n/2;
+    let centery = 0.0;
+    let centerx = 0.0;
+    let radius =  200.0;
+
+    let sp = 1.0;
+
+    for i in 0..n/2 {
+        let x: f32 = ((i as f32 % sqrn2) + rng.gen::<f32>())*sp;
+        let y: f32 = ((i as f32 / sqrn2) + rng.gen::<f32>())*sp;
+
+        v.push(Node::new(centerx - x, centery - y - radius, -32.0 + rng.gen::<f32>()/4.0, 3.0, 10.0, 5.0));
+    }
+
+    for i in 0..n/2 {
+        let x: f32 = ((i as f32 % sqrn2) + rng.gen::<f32>())*sp;
+        let y: f32 = ((i as f32 / sqrn2) + rng.gen::<f32>())*sp;
+
+        v.push(Node::new(centerx + x, centery + y + radius, 32.0 - rng.gen::<f32>()/4.0, -3.0, 10.0, -5.0));
+    }
+}
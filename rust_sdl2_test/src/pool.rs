@@ -0,0 +1,92 @@
+//! Long-lived worker threads, reused across frames.
+
+use std::thread;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender, Receiver};
+
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+enum Message {
+    Run(Job),
+    Stop,
+}
+
+
+/// Fixed set of threads pulling jobs from one shared queue.
+pub struct WorkerPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender:  Sender<Message>,
+}
+
+impl WorkerPool {
+    /// Spawns `size` workers (at least one).
+    pub fn new(size: usize) -> WorkerPool {
+        let size = size.max(1);
+        let (sender, receiver) = mpsc::channel::<Message>();
+        let receiver: Arc<Mutex<Receiver<Message>>> = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size).map(|_| {
+            let rx = receiver.clone();
+
+            thread::spawn(move || loop {
+                // lock is released as soon as a message is taken
+                let msg = rx.lock().unwrap().recv();
+
+                match msg {
+                    Ok(Message::Run(job)) => job(),
+                    Ok(Message::Stop) | Err(_) => break,
+                }
+            })
+        }).collect();
+
+        WorkerPool { workers, sender }
+    }
+
+    /// Spawns one worker per available CPU.
+    pub fn with_cpu_count() -> WorkerPool {
+        WorkerPool::new(thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+    }
+
+    pub fn size(&self) -> usize { self.workers.len() }
+
+    /// Queues `job` to be run by the first idle worker.
+    pub fn execute<F>(&self, job: F) where F: FnOnce() + Send + 'static {
+        self.sender.send(Message::Run(Box::new(job))).unwrap();
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        for _ in &self.workers {
+            let _ = self.sender.send(Message::Stop);
+        }
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_every_job_and_reuses_workers() {
+        let pool = WorkerPool::new(3);
+        let (tx, rx) = mpsc::channel();
+
+        for i in 0..100 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(i).unwrap());
+        }
+        drop(tx);
+
+        let mut got: Vec<i32> = rx.iter().collect();
+        got.sort();
+        assert_eq!(got, (0..100).collect::<Vec<_>>());
+        assert_eq!(pool.size(), 3);
+    }
+}
@@ -0,0 +1,216 @@
+//! Barnes–Hut quadtree for approximate O(n log n) force computation.
+//!
+//! Every cell keeps three monopoles: mass at the center of mass, and the
+//! positive and negative charges, each at its own center of charge. Charges
+//! of both signs are kept apart so a neutral cell still repels/attracts
+//! correctly when seen from nearby. Only long-range force laws see the
+//! monopoles; cells within reach of a short-range law are always opened.
+
+use node::Node;
+use force::{ForceLaw, pair_force};
+use boundary::Boundary;
+
+
+const LEAF_SIZE: usize = 4;  // max bodies in a leaf, summed exactly
+const MAX_DEPTH: u32   = 32; // stops splitting coincident bodies
+
+
+#[derive(Copy, Clone, Debug)]
+struct Cell {
+    cx: f32, cy: f32, half: f32, // square bounds
+    m:  f32, mx: f32, my: f32,   // total mass, center of mass
+    qp: f32, qpx: f32, qpy: f32, // positive charge, its center
+    qn: f32, qnx: f32, qny: f32, // negative charge, its center
+    start: usize, end: usize,    // range in `order`
+    children: [usize; 4],        // 0 means no child (root is never a child)
+}
+
+impl Cell {
+    fn is_leaf(&self) -> bool { self.children == [0; 4] }
+
+    fn contains(&self, x: f32, y: f32) -> bool {
+        (x - self.cx).abs() <= self.half && (y - self.cy).abs() <= self.half
+    }
+}
+
+
+/// Quadtree built over a snapshot of nodes; indices refer to that snapshot.
+pub struct QuadTree {
+    cells: Vec<Cell>,
+    order: Vec<usize>, // node indices, grouped by cell
+}
+
+impl QuadTree {
+    pub fn build(nodes: &[Node]) -> QuadTree {
+        let mut tree = QuadTree { cells: Vec::new(), order: (0..nodes.len()).collect() };
+        if nodes.is_empty() { return tree; }
+
+        // square bounding box of all nodes
+        let (mut minx, mut miny, mut maxx, mut maxy) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+        for n in nodes {
+            minx = minx.min(n.px); maxx = maxx.max(n.px);
+            miny = miny.min(n.py); maxy = maxy.max(n.py);
+        }
+        let half = ((maxx - minx).max(maxy - miny)*0.5).max(1.0)*1.0001;
+
+        tree.build_cell(nodes, 0, nodes.len(), ((minx + maxx)*0.5, (miny + maxy)*0.5), half, 0);
+        tree
+    }
+
+    pub fn len(&self) -> usize { self.order.len() }
+
+    pub fn is_empty(&self) -> bool { self.order.is_empty() }
+
+    fn build_cell(&mut self, nodes: &[Node], start: usize, end: usize,
+                  center: (f32, f32), half: f32, depth: u32) -> usize {
+        let mut cell = Cell {
+            cx: center.0, cy: center.1, half,
+            m: 0.0, mx: 0.0, my: 0.0,
+            qp: 0.0, qpx: 0.0, qpy: 0.0,
+            qn: 0.0, qnx: 0.0, qny: 0.0,
+            start, end,
+            children: [0; 4],
+        };
+
+        // accumulating monopoles
+        for &i in &self.order[start..end] {
+            let n = &nodes[i];
+            cell.m  += n.m; cell.mx += n.m*n.px; cell.my += n.m*n.py;
+            if n.c > 0.0 {
+                cell.qp += n.c; cell.qpx += n.c*n.px; cell.qpy += n.c*n.py;
+            } else if n.c < 0.0 {
+                cell.qn += n.c; cell.qnx += n.c*n.px; cell.qny += n.c*n.py;
+            }
+        }
+        if cell.m  != 0.0 { cell.mx  /= cell.m;  cell.my  /= cell.m;  }
+        if cell.qp != 0.0 { cell.qpx /= cell.qp; cell.qpy /= cell.qp; }
+        if cell.qn != 0.0 { cell.qnx /= cell.qn; cell.qny /= cell.qn; }
+
+        let idx = self.cells.len();
+        self.cells.push(cell);
+
+        if end - start <= LEAF_SIZE || depth >= MAX_DEPTH { return idx; }
+
+        // grouping bodies by quadrant: 0 = -x-y, 1 = +x-y, 2 = -x+y, 3 = +x+y
+        let quadrant = |n: &Node| (n.px >= center.0) as usize + 2*((n.py >= center.1) as usize);
+        self.order[start..end].sort_unstable_by_key(|&i| quadrant(&nodes[i]));
+
+        let h = half*0.5;
+        let mut s = start;
+        for q in 0..4 {
+            let mut e = s;
+            while e < end && quadrant(&nodes[self.order[e]]) == q { e += 1; }
+
+            if e > s {
+                let c = (center.0 + if q & 1 == 1 { h } else { -h },
+                         center.1 + if q & 2 == 2 { h } else { -h });
+                let child = self.build_cell(nodes, s, e, c, h, depth + 1);
+                self.cells[idx].children[q] = child;
+            }
+            s = e;
+        }
+
+        idx
+    }
+
+    /// Approximate total force on `n`; `nodes` must be the snapshot the tree was built from.
+    /// Cells of size `s` seen from distance `d` with `s/d < theta` are treated as monopoles,
+    /// `theta = 0` gives the exact sum. With periodic boundary the nearest image of
+    /// each body and of each monopole is used (cells are not split across the wrap).
+    pub fn force_on<L: ForceLaw>(&self, n: &Node, nodes: &[Node], theta: f32, boundary: Boundary,
+                                 laws: &[L]) -> (f32, f32) {
+        let from = (n.px, n.py);
+        let mut fv = (0.0, 0.0);
+        if self.cells.is_empty() { return fv; }
+
+        // reach of the short-range laws
+        let rc = laws.iter().filter_map(|l| l.cutoff()).fold(0.0, f32::max);
+
+        let mut stack = vec![0];
+        while let Some(ci) = stack.pop() {
+            let cell = &self.cells[ci];
+
+            if cell.is_leaf() {
+                for &i in &self.order[cell.start..cell.end] {
+                    let m = &nodes[i];
+                    if n.px == m.px && n.py == m.py { continue; }
+                    let (px, py) = boundary.image(from, (m.px, m.py));
+                    let f = pair_force(n, &Node { px, py, ..*m }, laws);
+                    fv.0 += f.0; fv.1 += f.1;
+                }
+                continue;
+            }
+
+            let com = boundary.image(from, (cell.mx, cell.my));
+            let (dx, dy) = (com.0 - n.px, com.1 - n.py);
+            let d = (dx*dx + dy*dy).sqrt();
+
+            // distance from `n` to the cell's box, for the short-range laws
+            let c = boundary.image(from, (cell.cx, cell.cy));
+            let (bx, by) = (((c.0 - n.px).abs() - cell.half).max(0.0), ((c.1 - n.py).abs() - cell.half).max(0.0));
+            let out_of_reach = bx*bx + by*by > rc*rc;
+
+            if !cell.contains(n.px, n.py) && 2.0*cell.half < theta*d && out_of_reach {
+                let qp = boundary.image(from, (cell.qpx, cell.qpy));
+                let qn = boundary.image(from, (cell.qnx, cell.qny));
+                let monopoles = [Node::new(com.0, com.1, 0.0, 0.0, cell.m, 0.0),
+                                 Node::new(qp.0, qp.1, 0.0, 0.0, 0.0, cell.qp),
+                                 Node::new(qn.0, qn.1, 0.0, 0.0, 0.0, cell.qn)];
+
+                for l in laws.iter().filter(|l| l.cutoff().is_none()) {
+                    for m in &monopoles {
+                        let f = pair_force(n, m, l);
+                        fv.0 += f.0; fv.1 += f.1;
+                    }
+                }
+            } else {
+                stack.extend(cell.children.iter().filter(|&&c| c != 0));
+            }
+        }
+
+        fv
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use node::init_nodes_vec;
+    use world::{node_force, Constants, seeded_rng};
+    use force::{Law, LennardJones};
+
+    fn cloud() -> Vec<Node> {
+        let mut v = Vec::new();
+        init_nodes_vec(&mut v, 400, &mut seeded_rng(1));
+        v
+    }
+
+    #[test]
+    fn theta_zero_matches_exact_sum() {
+        let v = cloud();
+        let tree = QuadTree::build(&v);
+        let k = Constants::default().laws();
+
+        for n in v.iter().step_by(37) {
+            let (e, a) = (node_force(n, &v, Boundary::Open, &k[..]), tree.force_on(n, &v, 0.0, Boundary::Open, &k));
+            assert!((e.0 - a.0).abs() <= 1e-2*e.0.abs().max(1.0));
+            assert!((e.1 - a.1).abs() <= 1e-2*e.1.abs().max(1.0));
+        }
+    }
+
+    #[test]
+    fn small_theta_is_close_to_exact() {
+        let v = cloud();
+        let tree = QuadTree::build(&v);
+        let mut k = Constants::default().laws();
+        k.push(Law::LennardJones(LennardJones { epsilon: 1.0, sigma: 4.0, cutoff: 10.0 }));
+
+        for n in v.iter().step_by(37) {
+            let (e, a) = (node_force(n, &v, Boundary::Open, &k[..]), tree.force_on(n, &v, 0.5, Boundary::Open, &k));
+            let err = ((e.0 - a.0).powi(2) + (e.1 - a.1).powi(2)).sqrt();
+            let mag = (e.0*e.0 + e.1*e.1).sqrt();
+            assert!(err <= 0.05*mag.max(1.0), "err {} vs |f| {}", err, mag);
+        }
+    }
+}
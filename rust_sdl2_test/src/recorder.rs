@@ -0,0 +1,211 @@
+//! Recording of rendered frames to a numbered PNG sequence or an animated GIF.
+//!
+//! The recorder only sees RGB24 buffers, the front-end reads them back
+//! from whatever it rendered. Frames are counted as they are presented, so
+//! with a fixed time step the same options always capture the same frames.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use gif;
+use png;
+
+
+/// Playback rate of recordings, used for GIF frame delays and `max_secs`.
+pub const RECORD_FPS: u32 = 25;
+
+
+#[derive(Debug)]
+pub enum RecordError {
+    Io(io::Error),
+    Png(png::EncodingError),
+    Gif(gif::EncodingError),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecordError::Io(ref e)  => write!(f, "recording i/o failed: {}", e),
+            RecordError::Png(ref e) => write!(f, "cannot encode png: {}", e),
+            RecordError::Gif(ref e) => write!(f, "cannot encode gif: {}", e),
+        }
+    }
+}
+
+impl ::std::error::Error for RecordError {}
+
+impl From<io::Error> for RecordError {
+    fn from(e: io::Error) -> RecordError { RecordError::Io(e) }
+}
+
+impl From<png::EncodingError> for RecordError {
+    fn from(e: png::EncodingError) -> RecordError { RecordError::Png(e) }
+}
+
+impl From<gif::EncodingError> for RecordError {
+    fn from(e: gif::EncodingError) -> RecordError { RecordError::Gif(e) }
+}
+
+
+/// Which frames get recorded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RecordOptions {
+    pub every:    u64,         // records every `every`-th presented frame
+    pub max_secs: Option<f32>, // stops after this much playback time at `RECORD_FPS`
+}
+
+impl Default for RecordOptions {
+    fn default() -> RecordOptions {
+        RecordOptions { every: 1, max_secs: None }
+    }
+}
+
+impl RecordOptions {
+    /// Number of frames to write, if limited.
+    pub fn max_frames(&self) -> Option<u64> {
+        self.max_secs.map(|s| (s*RECORD_FPS as f32).round() as u64)
+    }
+}
+
+
+enum Sink {
+    Png(PathBuf),                                        // prefix of `<prefix>_000000.png`
+    Gif(Option<(gif::Encoder<BufWriter<File>>, u32, u32)>, PathBuf), // created with the first frame size
+}
+
+
+pub struct Recorder {
+    sink:    Sink,
+    options: RecordOptions,
+    seen:    u64,
+    written: u64,
+}
+
+impl Recorder {
+    /// `.gif` paths record an animated GIF, any other path is the prefix of a PNG sequence.
+    pub fn new<P: AsRef<Path>>(path: P, options: RecordOptions) -> Recorder {
+        let path = path.as_ref().to_path_buf();
+        let sink = match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("gif") => Sink::Gif(None, path),
+            _ => Sink::Png(path),
+        };
+        Recorder { sink, options, seen: 0, written: 0 }
+    }
+
+    /// Counts one presented frame; returns whether it should be passed to `add_frame`.
+    pub fn tick(&mut self) -> bool {
+        let due = !self.is_done() && self.seen.is_multiple_of(self.options.every.max(1));
+        self.seen += 1;
+        due
+    }
+
+    /// Whether the maximal duration has been recorded.
+    pub fn is_done(&self) -> bool {
+        self.options.max_frames().is_some_and(|max| self.written >= max)
+    }
+
+    pub fn frames(&self) -> u64 { self.written }
+
+    /// Where the recording goes (the GIF file, or the PNG prefix).
+    pub fn path(&self) -> &Path {
+        match self.sink {
+            Sink::Png(ref p) | Sink::Gif(_, ref p) => p,
+        }
+    }
+
+    /// Appends a frame of tightly packed RGB24 pixels.
+    pub fn add_frame(&mut self, w: u32, h: u32, rgb: &[u8]) -> Result<(), RecordError> {
+        match self.sink {
+            Sink::Png(ref prefix) => {
+                let name = format!("{}_{:06}.png", prefix.display(), self.written);
+                let mut enc = png::Encoder::new(BufWriter::new(File::create(name)?), w, h);
+                enc.set_color(png::ColorType::Rgb);
+                enc.set_depth(png::BitDepth::Eight);
+                enc.write_header()?.write_image_data(rgb)?;
+            },
+            Sink::Gif(ref mut enc, ref path) => {
+                if enc.is_none() {
+                    let mut e = gif::Encoder::new(BufWriter::new(File::create(path)?), w as u16, h as u16, &[])?;
+                    e.set_repeat(gif::Repeat::Infinite)?;
+                    *enc = Some((e, w, h));
+                }
+                let (ref mut e, gw, gh) = *enc.as_mut().unwrap();
+
+                // all frames keep the first size, cropped or padded with black
+                let resized;
+                let rgb = if (w, h) == (gw, gh) { rgb } else {
+                    resized = fit_rgb(rgb, (w, h), (gw, gh));
+                    &resized[..]
+                };
+
+                let mut frame = gif::Frame::from_rgb_speed(gw as u16, gh as u16, rgb, 10);
+                frame.delay = (100/RECORD_FPS) as u16; // in 1/100 s
+                e.write_frame(&frame)?;
+            },
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Closes the recording, returns the number of frames written.
+    pub fn finish(self) -> u64 {
+        // the GIF trailer is written when the encoder is dropped
+        self.written
+    }
+}
+
+
+// copies the top left of an RGB24 image into a black one of another size
+fn fit_rgb(rgb: &[u8], from: (u32, u32), to: (u32, u32)) -> Vec<u8> {
+    let mut out = vec![0u8; (to.0*to.1*3) as usize];
+    let (w, h) = (from.0.min(to.0) as usize, from.1.min(to.1) as usize);
+    for y in 0..h {
+        let src = y*from.0 as usize*3;
+        let dst = y*to.0 as usize*3;
+        out[dst..dst + w*3].copy_from_slice(&rgb[src..src + w*3]);
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn frame_skip_and_max_duration() {
+        let opts = RecordOptions { every: 3, max_secs: Some(2.0/RECORD_FPS as f32) };
+        let mut r = Recorder::new(env::temp_dir().join("sdl2_test_skip"), opts);
+
+        let due: Vec<bool> = (0..6).map(|_| {
+            let due = r.tick();
+            if due { r.written += 1; }
+            due
+        }).collect();
+
+        assert_eq!(due, vec![true, false, false, true, false, false]);
+        assert!(r.is_done() && !r.tick());
+    }
+
+    #[test]
+    fn writes_png_sequence_and_gif() {
+        let dir = env::temp_dir();
+        let rgb = [255u8, 128, 50, 50, 128, 255].repeat(2); // 2x2 pixels
+
+        let mut png = Recorder::new(dir.join("sdl2_test_rec"), RecordOptions::default());
+        for _ in 0..2 { png.add_frame(2, 2, &rgb).unwrap(); }
+        assert_eq!(png.finish(), 2);
+        assert!(dir.join("sdl2_test_rec_000001.png").exists());
+
+        let path = dir.join("sdl2_test_rec.gif");
+        let mut gif = Recorder::new(&path, RecordOptions::default());
+        for _ in 0..3 { gif.add_frame(2, 2, &rgb).unwrap(); }
+        gif.add_frame(1, 1, &rgb[..3]).unwrap(); // window resized while recording
+        assert_eq!(gif.finish(), 4);
+        assert_eq!(&fs::read(&path).unwrap()[..6], b"GIF89a");
+    }
+}
@@ -0,0 +1,387 @@
+//! Scene description files (TOML) for initial conditions and emitters.
+//!
+//! ```toml
+//! dt = 0.01
+//! max_particles = 2048
+//! integrator = "Boris"    # optional, semi-implicit Euler by default
+//!
+//! [constants]             # gravity and Coulomb, unless [[force]] is given
+//! gravity = 10.0
+//! coulomb = 10.0
+//! dthr    = 4.0
+//!
+//! [boundary]              # optional, open by default; also the box of the X toggle
+//! kind = "Periodic"
+//! w    = 800.0
+//! h    = 600.0
+//!
+//! [solver]                # optional, exact by default
+//! kind   = "CellList"
+//! cutoff = 10.0
+//!
+//! [magnetic]              # optional out-of-plane field, see `Magnetic`
+//! kind = "Uniform"
+//! bz   = 0.1
+//!
+//! [timestep]              # optional adaptive dt, see `AdaptiveDt`
+//! max_dt = 0.01
+//!
+//! [[force]]               # optional list of force laws, summed
+//! kind = "Plummer"
+//! g    = 10.0
+//! eps  = 2.0
+//!
+//! [[group]]               # block of particles on a jittered square grid
+//! count    = 1024
+//! center   = [0.0, -200.0]
+//! spacing  = 1.0
+//! velocity = [-32.0, 3.0]
+//! mass     = 10.0
+//! charge   = 5.0
+//!
+//! [[emitter]]
+//! position = [-200.0, 32.0]
+//! velocity = [10.0, 10.0]
+//! mass     = 20.0
+//! charge   = -10.0
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use rand::Rng;
+use toml;
+
+use node::{Node, emit_node};
+use world::{World, Emitter, Solver, Constants, random_seed};
+use force::Law;
+use timestep::AdaptiveDt;
+use integrator::Integrator;
+use magnetic::Magnetic;
+use boundary::Boundary;
+
+
+/// Scene shipped with the binary, used when no scene file is given.
+pub const DEFAULT_SCENE: &str = include_str!("../scenes/two_emitters.toml");
+
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Invalid(String), // parses, but cannot be run
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SceneError::Io(ref e)    => write!(f, "cannot read scene: {}", e),
+            SceneError::Parse(ref e) => write!(f, "invalid scene: {}", e),
+            SceneError::Invalid(ref e) => write!(f, "invalid scene: {}", e),
+        }
+    }
+}
+
+impl ::std::error::Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+    fn from(e: io::Error) -> SceneError { SceneError::Io(e) }
+}
+
+impl From<toml::de::Error> for SceneError {
+    fn from(e: toml::de::Error) -> SceneError { SceneError::Parse(e) }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    #[serde(default = "default_dt")]
+    pub dt: f32,
+    #[serde(default)]
+    pub max_particles: Option<usize>,
+    #[serde(default)]
+    pub integrator: Option<Integrator>,
+    #[serde(default)]
+    pub boundary: Option<Boundary>,
+    #[serde(default)]
+    pub solver: Option<Solver>,
+    #[serde(default)]
+    pub magnetic: Option<Magnetic>,
+    #[serde(default)]
+    pub timestep: Option<AdaptiveDt>, // adaptive steps; `dt` then only sets the simulated time per window frame
+    #[serde(default)]
+    pub constants: SceneConstants,
+    #[serde(default, rename = "force")]
+    pub forces: Vec<Law>,
+    #[serde(default, rename = "group")]
+    pub groups: Vec<Group>,
+    #[serde(default, rename = "emitter")]
+    pub emitters: Vec<SceneEmitter>,
+}
+
+fn default_dt() -> f32 { 0.01 }
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneConstants {
+    pub gravity: f32,
+    pub coulomb: f32,
+    pub dthr:    f32,
+}
+
+impl Default for SceneConstants {
+    fn default() -> SceneConstants {
+        let k = Constants::default();
+        SceneConstants { gravity: k.gravity, coulomb: k.coulomb, dthr: k.dthr }
+    }
+}
+
+
+/// Block of `count` particles on a square grid around `center`,
+/// each position and velocity jittered by a random offset.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    pub count:    u32,
+    #[serde(default)]
+    pub center:   (f32, f32),
+    #[serde(default = "one")]
+    pub spacing:  f32,
+    #[serde(default = "one")]
+    pub jitter:   f32,
+    #[serde(default)]
+    pub velocity: (f32, f32),
+    #[serde(default)]
+    pub velocity_jitter: f32,
+    pub mass:     f32,
+    #[serde(default)]
+    pub charge:   f32,
+}
+
+fn one() -> f32 { 1.0 }
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneEmitter {
+    pub position: (f32, f32),
+    #[serde(default)]
+    pub velocity: (f32, f32),
+    pub mass:     f32,
+    #[serde(default)]
+    pub charge:   f32,
+    #[serde(default = "one")]
+    pub jitter:   f32,
+    #[serde(default = "every_step")]
+    pub every:    u64,
+}
+
+fn every_step() -> u64 { 1 }
+
+
+impl Scene {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Scene::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Scene, SceneError> {
+        let scene: Scene = toml::from_str(text)?;
+        if let Some(a) = scene.timestep { a.check().map_err(SceneError::Invalid)?; }
+        if let Some(s) = scene.solver { s.check(&scene.laws()).map_err(SceneError::Invalid)?; }
+        Ok(scene)
+    }
+
+    pub fn constants(&self) -> Constants {
+        let k = &self.constants;
+        Constants { gravity: k.gravity, coulomb: k.coulomb, dthr: k.dthr }
+    }
+
+    /// Force laws of the scene: the `[[force]]` list, or the constants if there is none.
+    pub fn laws(&self) -> Vec<Law> {
+        if self.forces.is_empty() { self.constants().laws() } else { self.forces.clone() }
+    }
+
+    /// Builds a world with this scene's particles, emitters, force laws and cap.
+    pub fn build(&self) -> World { self.build_seeded(random_seed()) }
+
+    /// Like `build`; groups and later emissions draw from one generator seeded
+    /// with `seed`, so the same seed gives the same run.
+    pub fn build_seeded(&self, seed: u64) -> World {
+        let mut world = World::new();
+        if let Some(max) = self.max_particles { world.set_max_nodes(max); }
+        world.set_laws(self.laws());
+        if let Some(solver) = self.solver { world.set_solver(solver); }
+        if let Some(boundary) = self.boundary { world.set_boundary(boundary); }
+        if let Some(integrator) = self.integrator { world.set_integrator(integrator); }
+        world.set_magnetic(self.magnetic);
+        world.set_adaptive(self.timestep);
+        world.set_seed(seed);
+
+        let mut nodes = Vec::new();
+        for g in &self.groups {
+            g.spawn(&mut nodes, world.rng_mut());
+        }
+        world.add_nodes(nodes);
+
+        for e in &self.emitters {
+            world.add_emitter(Emitter {
+                jitter: e.jitter,
+                every:  e.every,
+                ..Emitter::new(e.position.0, e.position.1, e.velocity.0, e.velocity.1, e.mass, e.charge)
+            });
+        }
+
+        world
+    }
+}
+
+
+impl Group {
+    /// Appends the particles of this group to `v`.
+    pub fn spawn<R: Rng>(&self, v: &mut Vec<Node>, rng: &mut R) {
+        let side = (self.count as f32).sqrt().ceil().max(1.0) as u32;
+        let half = 0.5*(side as f32 - 1.0)*self.spacing;
+
+        for i in 0..self.count {
+            let (gx, gy) = ((i % side) as f32, (i / side) as f32);
+            let x = self.center.0 - half + gx*self.spacing + rng.gen::<f32>()*self.jitter;
+            let y = self.center.1 - half + gy*self.spacing + rng.gen::<f32>()*self.jitter;
+            let vx = self.velocity.0 + (rng.gen::<f32>() - 0.5)*self.velocity_jitter;
+            let vy = self.velocity.1 + (rng.gen::<f32>() - 0.5)*self.velocity_jitter;
+
+            emit_node(v, x, y, vx, vy, self.mass, self.charge);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use force::{ForceLaw, LennardJones};
+
+    #[test]
+    fn default_scene_parses() {
+        let scene = Scene::parse(DEFAULT_SCENE).unwrap();
+        let world = scene.build();
+
+        assert_eq!(scene.dt, 0.01);
+        assert_eq!(world.max_nodes(), 2048);
+        assert_eq!(world.emitters().len(), 2);
+        assert!(world.is_empty());
+    }
+
+    #[test]
+    fn lennard_jones_scene_uses_cell_list() {
+        let scene = Scene::parse(include_str!("../scenes/lennard_jones.toml")).unwrap();
+        let world = scene.build_seeded(1);
+
+        assert_eq!(world.solver(), Solver::CellList { cutoff: 10.0 });
+        assert_eq!(world.laws()[..].cutoff(), Some(10.0));
+        assert_eq!(world.len(), 4096);
+    }
+
+    #[test]
+    fn magnetized_scene_uses_boris() {
+        let scene = Scene::parse(include_str!("../scenes/magnetized.toml")).unwrap();
+        let world = scene.build_seeded(1);
+
+        assert_eq!(world.integrator(), Integrator::Boris);
+        assert!(world.magnetic().is_some());
+        assert_eq!(world.emitters().len(), 2);
+    }
+
+    #[test]
+    fn groups_and_constants_are_built() {
+        let scene = Scene::parse(r#"
+            dt = 0.02
+            [boundary]
+            kind = "Reflective"
+            w    = 300.0
+            h    = 200.0
+            [constants]
+            gravity = 1.0
+            [timestep]
+            min_dt = 0.001
+            [[group]]
+            count  = 10
+            mass   = 2.0
+            charge = -1.0
+        "#).unwrap();
+        let world = scene.build();
+
+        assert_eq!(scene.dt, 0.02);
+        assert_eq!(world.boundary(), Boundary::Reflective { w: 300.0, h: 200.0 });
+        assert_eq!(world.adaptive(), Some(AdaptiveDt { min_dt: 0.001, ..AdaptiveDt::default() }));
+        assert_eq!(world.len(), 10);
+        assert_eq!(world.laws(), &Constants { gravity: 1.0, ..Constants::default() }.laws()[..]);
+        assert!(world.nodes().iter().all(|n| n.m == 2.0 && n.c == -1.0));
+    }
+
+    #[test]
+    fn force_list_overrides_constants() {
+        let scene = Scene::parse(r#"
+            [constants]
+            gravity = 1.0
+            [[force]]
+            kind    = "LennardJones"
+            epsilon = 1.0
+            sigma   = 4.0
+            cutoff  = 10.0
+        "#).unwrap();
+
+        assert_eq!(scene.build().laws(), &[Law::LennardJones(LennardJones { epsilon: 1.0, sigma: 4.0, cutoff: 10.0 })]);
+        assert_eq!(scene.build().solver(), Solver::Exact);
+    }
+
+    #[test]
+    fn same_seed_gives_identical_runs() {
+        let scene = Scene::parse(DEFAULT_SCENE).unwrap();
+        let run = |seed| {
+            let mut w = scene.build_seeded(seed);
+            for _ in 0..20 { w.step(scene.dt); }
+            w.nodes()
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn timestep_bounds_out_of_order_are_rejected() {
+        // min_dt above the default max_dt
+        match Scene::parse("[timestep]\nmin_dt = 0.05\n") {
+            Err(SceneError::Invalid(_)) => {},
+            other => panic!("expected invalid scene, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cell_list_with_long_range_laws_is_rejected() {
+        // the default constants are gravity and Coulomb
+        match Scene::parse("[solver]\nkind = \"CellList\"\ncutoff = 10.0\n") {
+            Err(SceneError::Invalid(_)) => {},
+            other => panic!("expected invalid scene, got {:?}", other),
+        }
+
+        let yukawa = "[solver]\nkind = \"CellList\"\ncutoff = 10.0\n\
+                      [[force]]\nkind = \"Yukawa\"\nk = 1.0\nlambda = 2.0\ndthr = 1.0\n";
+        assert!(Scene::parse(yukawa).is_err());
+        assert!(Scene::parse(&format!("{}cutoff = 20.0\n", yukawa)).is_err());
+        assert!(Scene::parse(&format!("{}cutoff = 8.0\n", yukawa)).is_ok());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        match Scene::parse("dt = 0.01\nparticles = 3\n") {
+            Err(SceneError::Parse(_)) => {},
+            other => panic!("expected parse error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Full simulation snapshots, saved as versioned TOML files.
+//!
+//! Holds every particle, the emitters, the step counter, the random seed and all
+//! simulation parameters, so a run can be resumed exactly where it was saved.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use toml;
+
+use node::Node;
+use world::{World, Emitter, Solver, Constants};
+use force::Law;
+use timestep::AdaptiveDt;
+use magnetic::Magnetic;
+use integrator::Integrator;
+use collision::Collisions;
+use boundary::Boundary;
+
+
+/// Format version written to new snapshots. Version 1 snapshots (gravity and Coulomb
+/// in a `[constants]` table instead of `[[force]]`) are converted on load, others rejected.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Write(toml::ser::Error),
+    Version(u32),
+    Invalid(String), // parses, but cannot be run
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::Io(ref e)    => write!(f, "snapshot i/o failed: {}", e),
+            SnapshotError::Parse(ref e) => write!(f, "invalid snapshot: {}", e),
+            SnapshotError::Write(ref e) => write!(f, "cannot serialize snapshot: {}", e),
+            SnapshotError::Invalid(ref e) => write!(f, "invalid snapshot: {}", e),
+            SnapshotError::Version(v)   => write!(f, "unsupported snapshot version {} (expected {})", v, SNAPSHOT_VERSION),
+        }
+    }
+}
+
+impl ::std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> SnapshotError { SnapshotError::Io(e) }
+}
+
+impl From<toml::de::Error> for SnapshotError {
+    fn from(e: toml::de::Error) -> SnapshotError { SnapshotError::Parse(e) }
+}
+
+impl From<toml::ser::Error> for SnapshotError {
+    fn from(e: toml::ser::Error) -> SnapshotError { SnapshotError::Write(e) }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version:    u32,
+    pub time:       f32,
+    pub steps:      u64,
+    pub dt:         f32,
+    pub max_nodes:  Option<usize>, // none means unlimited
+    #[serde(default)]
+    pub seed:       Option<i64>,   // bits of the u64 seed (TOML integers are signed), none in old snapshots
+    #[serde(default)]
+    pub next_id:    Option<u64>,   // none in old snapshots, then the highest saved id + 1
+    pub integrator: Integrator,
+    pub collisions: Collisions,
+    // tables go after plain values in TOML
+    pub solver:     Solver,
+    pub boundary:   Boundary,
+    #[serde(default)]
+    pub adaptive:   Option<AdaptiveDt>, // none means fixed `dt`
+    #[serde(default)]
+    pub magnetic:   Option<Magnetic>,
+    #[serde(default, rename = "force", skip_serializing_if = "Vec::is_empty")]
+    pub laws:       Vec<Law>,
+    #[serde(default, rename = "emitter", skip_serializing_if = "Vec::is_empty")]
+    pub emitters:   Vec<Emitter>,
+    #[serde(default, rename = "node", skip_serializing_if = "Vec::is_empty")]
+    pub nodes:      Vec<Node>,
+}
+
+
+impl Snapshot {
+    /// Captures `world`; `dt` is the step the front-end uses.
+    pub fn capture(world: &World, dt: f32) -> Snapshot {
+        Snapshot {
+            version:    SNAPSHOT_VERSION,
+            time:       world.time(),
+            steps:      world.steps(),
+            dt,
+            max_nodes:  if world.max_nodes() == usize::MAX { None } else { Some(world.max_nodes()) },
+            seed:       Some(world.seed() as i64),
+            next_id:    Some(world.next_id()),
+            integrator: world.integrator(),
+            collisions: world.collisions(),
+            solver:     world.solver(),
+            boundary:   world.boundary(),
+            adaptive:   world.adaptive(),
+            magnetic:   world.magnetic(),
+            laws:       world.laws().to_vec(),
+            emitters:   world.emitters().to_vec(),
+            nodes:      world.nodes(),
+        }
+    }
+
+    /// Builds a new world in the saved state.
+    pub fn restore(&self) -> World {
+        let mut world = World::with_max_nodes(self.max_nodes.unwrap_or(usize::MAX));
+        world.set_clock(self.time, self.steps);
+        if let Some(seed) = self.seed { world.set_seed(seed as u64); }
+        world.set_laws(self.laws.clone());
+        world.set_solver(self.solver);
+        world.set_integrator(self.integrator);
+        world.set_collisions(self.collisions);
+        world.set_boundary(self.boundary);
+        world.set_adaptive(self.adaptive);
+        world.set_magnetic(self.magnetic);
+        for e in &self.emitters { world.add_emitter(*e); }
+        world.add_nodes(self.nodes.iter().cloned());
+        if let Some(id) = self.next_id { world.set_next_id(id); }
+        world
+    }
+
+    pub fn to_string(&self) -> Result<String, SnapshotError> {
+        Ok(toml::to_string(self)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Snapshot, SnapshotError> {
+        // checking the version first, the rest of the layout may differ
+        #[derive(Deserialize)]
+        struct Header { version: u32, constants: Option<Constants> }
+        let header: Header = toml::from_str(text)?;
+        if header.version != SNAPSHOT_VERSION && header.version != 1 {
+            return Err(SnapshotError::Version(header.version));
+        }
+
+        let mut snap: Snapshot = toml::from_str(text)?;
+        if header.version == 1 {
+            snap.version = SNAPSHOT_VERSION;
+            snap.laws    = header.constants.unwrap_or_default().laws();
+        }
+        if let Some(a) = snap.adaptive { a.check().map_err(SnapshotError::Invalid)?; }
+        snap.solver.check(&snap.laws).map_err(SnapshotError::Invalid)?;
+        Ok(snap)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        let text = self.to_string()?;
+        File::create(path)?.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Snapshot::parse(&text)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use node::init_nodes_vec;
+    use world::seeded_rng;
+    use scene::{Scene, DEFAULT_SCENE};
+    use force::{Plummer, Coulomb};
+
+    #[test]
+    fn roundtrip_restores_identical_state() {
+        let mut w = World::with_max_nodes(300);
+        let mut v = Vec::new();
+        init_nodes_vec(&mut v, 100, &mut seeded_rng(1));
+        w.add_nodes(v);
+        w.add_emitter(Emitter::new(-200.0, 32.0, 10.0, 10.0, 20.0, -10.0));
+        w.set_solver(Solver::BarnesHut { theta: 0.7 });
+        w.set_integrator(Integrator::VelocityVerlet);
+        w.set_boundary(Boundary::Periodic { w: 800.0, h: 600.0 });
+        w.set_adaptive(Some(AdaptiveDt::default()));
+        w.set_magnetic(Some(Magnetic::Gradient { bz: 0.1, gx: 0.001, gy: 0.0 }));
+        w.set_laws(vec![Law::Plummer(Plummer { g: 10.0, eps: 2.0 }), Law::Coulomb(Coulomb { k: 5.0, dthr: 4.0 })]);
+        for _ in 0..5 { w.step(0.01); }
+
+        let snap = Snapshot::capture(&w, 0.01);
+        let back = Snapshot::parse(&snap.to_string().unwrap()).unwrap();
+        assert_eq!(back, snap);
+
+        let r = back.restore();
+        assert_eq!((r.time(), r.steps(), r.max_nodes()), (w.time(), w.steps(), 300));
+        assert_eq!(r.nodes(), w.nodes());
+        assert_eq!(r.boundary(), w.boundary());
+        assert_eq!(r.laws(), w.laws());
+        assert_eq!(r.adaptive(), w.adaptive());
+        assert_eq!(r.magnetic(), w.magnetic());
+    }
+
+    #[test]
+    fn resumed_run_matches_uninterrupted_one() {
+        // emitted nodes overlap their predecessors, so the newest ids keep merging away
+        let scene = Scene::parse(DEFAULT_SCENE).unwrap();
+        let mut whole = scene.build_seeded(u64::MAX - 3);
+        whole.set_collisions(Collisions::Merge);
+        for _ in 0..30 { whole.step(scene.dt); }
+
+        let mut first = scene.build_seeded(u64::MAX - 3);
+        first.set_collisions(Collisions::Merge);
+        for _ in 0..15 { first.step(scene.dt); }
+        assert!(first.particles().id.iter().all(|&id| id + 1 < first.next_id()));
+        let text = Snapshot::capture(&first, scene.dt).to_string().unwrap();
+        let mut resumed = Snapshot::parse(&text).unwrap().restore();
+        for _ in 0..15 { resumed.step(scene.dt); }
+
+        assert_eq!(resumed.seed(), whole.seed());
+        assert_eq!(resumed.next_id(), whole.next_id());
+        assert_eq!(resumed.nodes(), whole.nodes());
+    }
+
+    #[test]
+    fn version_1_constants_become_laws() {
+        let snap = Snapshot::parse(r#"
+            version    = 1
+            time       = 0.5
+            steps      = 50
+            dt         = 0.01
+            integrator = "Rk4"
+            collisions = "Off"
+            [constants]
+            gravity = 2.0
+            coulomb = 3.0
+            dthr    = 4.0
+            [solver]
+            kind = "Exact"
+            [boundary]
+            kind = "Open"
+            [[node]]
+            m  = 10.0
+            c  = 5.0
+            px = 1.0
+            py = 2.0
+            vx = 0.0
+            vy = 0.0
+            ax = 0.0
+            ay = 0.0
+            fx = 0.0
+            fy = 0.0
+        "#).unwrap();
+
+        assert_eq!(snap.version, SNAPSHOT_VERSION);
+        assert_eq!(snap.laws, Constants { gravity: 2.0, coulomb: 3.0, dthr: 4.0 }.laws());
+        let w = snap.restore();
+        assert_eq!((w.steps(), w.len(), w.integrator()), (50, 1, Integrator::Rk4));
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut snap = Snapshot::capture(&World::new(), 0.01);
+        snap.version = SNAPSHOT_VERSION + 1;
+
+        match Snapshot::parse(&snap.to_string().unwrap()) {
+            Err(SnapshotError::Version(v)) => assert_eq!(v, SNAPSHOT_VERSION + 1),
+            other => panic!("expected version error, got {:?}", other),
+        }
+    }
+    #[test]
+    fn cell_list_with_long_range_laws_is_rejected() {
+        let mut w = World::new();
+        w.set_solver(Solver::CellList { cutoff: 10.0 }); // default laws: gravity and Coulomb
+
+        match Snapshot::parse(&Snapshot::capture(&w, 0.01).to_string().unwrap()) {
+            Err(SnapshotError::Invalid(_)) => {},
+            other => panic!("expected invalid snapshot, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Structure-of-arrays particle storage and the vectorised force kernel.
+//!
+//! `World` keeps its nodes in `Particles`: every node field lives in its own
+//! array, so integrators stream through the fields they update and the force
+//! loop reads only positions, masses and charges, four nodes per SIMD register. On x86_64
+//! the kernel uses SSE2 (always present there); elsewhere a scalar loop is
+//! used, written so the compiler can auto-vectorise it. The kernel covers
+//! gravity and Coulomb sharing one clamp distance, which is the default
+//! force; other laws go through the generic `ForceLaw` path.
+
+use node::Node;
+use force::Law;
+
+
+/// Particles as separate arrays of their fields; `node(i)` and `to_nodes`
+/// give back the `Node` layout. Nodes keep their order, removals included.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Particles {
+    pub id: Vec<u64>,                   // stable identity, see `Node::id`
+    pub m:  Vec<f32>, pub c:  Vec<f32>, // mass, charge
+    pub px: Vec<f32>, pub py: Vec<f32>, // position
+    pub vx: Vec<f32>, pub vy: Vec<f32>, // velocity
+    pub ax: Vec<f32>, pub ay: Vec<f32>, // acceleration
+    pub fx: Vec<f32>, pub fy: Vec<f32>, // force
+}
+
+impl Particles {
+    pub fn from_nodes(nodes: &[Node]) -> Particles {
+        let mut p = Particles::default();
+        p.fill_from(nodes);
+        p
+    }
+
+    /// Replaces the contents with `nodes`, reusing the arrays.
+    pub fn fill_from(&mut self, nodes: &[Node]) {
+        self.clear();
+        for n in nodes { self.push(*n); }
+    }
+
+    pub fn clear(&mut self) {
+        self.id.clear();
+        for a in self.arrays_mut() { a.clear(); }
+    }
+
+    pub fn push(&mut self, n: Node) {
+        self.id.push(n.id);
+        self.m.push(n.m);   self.c.push(n.c);
+        self.px.push(n.px); self.py.push(n.py);
+        self.vx.push(n.vx); self.vy.push(n.vy);
+        self.ax.push(n.ax); self.ay.push(n.ay);
+        self.fx.push(n.fx); self.fy.push(n.fy);
+    }
+
+    /// Overwrites node `i`.
+    pub fn set(&mut self, i: usize, n: Node) {
+        self.id[i] = n.id;
+        self.m[i]  = n.m;  self.c[i]  = n.c;
+        self.px[i] = n.px; self.py[i] = n.py;
+        self.vx[i] = n.vx; self.vy[i] = n.vy;
+        self.ax[i] = n.ax; self.ay[i] = n.ay;
+        self.fx[i] = n.fx; self.fy[i] = n.fy;
+    }
+
+    /// Keeps node `i` only if `keep[i]`, preserving the order of the others.
+    pub fn retain(&mut self, keep: &[bool]) {
+        let mut k = 0;
+        self.id.retain(|_| { k += 1; keep[k - 1] });
+        for a in self.arrays_mut() {
+            let mut k = 0;
+            a.retain(|_| { k += 1; keep[k - 1] });
+        }
+    }
+
+    pub fn len(&self) -> usize { self.m.len() }
+
+    pub fn is_empty(&self) -> bool { self.m.is_empty() }
+
+    /// Node `i` in the array-of-structs layout.
+    pub fn node(&self, i: usize) -> Node {
+        Node {
+            id: self.id[i],
+            m:  self.m[i],  c:  self.c[i],
+            px: self.px[i], py: self.py[i],
+            vx: self.vx[i], vy: self.vy[i],
+            ax: self.ax[i], ay: self.ay[i],
+            fx: self.fx[i], fy: self.fy[i],
+        }
+    }
+
+    /// All nodes in the array-of-structs layout, e.g. for rendering.
+    pub fn to_nodes(&self) -> Vec<Node> {
+        (0..self.len()).map(|i| self.node(i)).collect()
+    }
+
+    fn arrays_mut(&mut self) -> [&mut Vec<f32>; 10] {
+        [&mut self.m, &mut self.c, &mut self.px, &mut self.py, &mut self.vx,
+         &mut self.vy, &mut self.ax, &mut self.ay, &mut self.fx, &mut self.fy]
+    }
+}
+
+impl Extend<Node> for Particles {
+    fn extend<I: IntoIterator<Item = Node>>(&mut self, nodes: I) {
+        for n in nodes { self.push(n); }
+    }
+}
+
+
+/// Coefficients of the forces the kernel evaluates:
+/// `(g*m1*m2 - k*c1*c2)/d^2`, with `d` clamped to `dthr`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Kernel {
+    pub g:    f32,
+    pub k:    f32,
+    pub dthr: f32,
+}
+
+impl Kernel {
+    /// Kernel equivalent to `laws`, if they are only gravity and Coulomb
+    /// with one clamp distance.
+    pub fn from_laws(laws: &[Law]) -> Option<Kernel> {
+        let mut kernel: Option<Kernel> = None;
+        for law in laws {
+            let (g, k, dthr) = match *law {
+                Law::Gravity(l) => (l.g, 0.0, l.dthr),
+                Law::Coulomb(l) => (0.0, l.k, l.dthr),
+                _ => return None,
+            };
+            kernel = match kernel {
+                None => Some(Kernel { g, k, dthr }),
+                Some(kn) if kn.dthr == dthr => Some(Kernel { g: kn.g + g, k: kn.k + k, dthr }),
+                Some(_) => return None,
+            };
+        }
+        kernel
+    }
+
+    /// Total force on particle `i` from all others; coincident particles are skipped.
+    pub fn force_on(&self, p: &Particles, i: usize) -> (f32, f32) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            // SSE2 is part of the x86_64 baseline
+            unsafe { self.force_on_sse2(p, i) }
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            self.force_on_scalar(p, i, 0)
+        }
+    }
+
+    /// Portable version of `force_on`, over particles from `start` on.
+    pub fn force_on_scalar(&self, p: &Particles, i: usize, start: usize) -> (f32, f32) {
+        let (x, y, gm, kc) = (p.px[i], p.py[i], self.g*p.m[i], self.k*p.c[i]);
+        let mut fv = (0.0, 0.0);
+
+        let others = p.px[start..].iter().zip(&p.py[start..]).zip(p.m[start..].iter().zip(&p.c[start..]));
+        for ((&ox, &oy), (&om, &oc)) in others {
+            let (dx, dy) = (ox - x, oy - y);
+            let d = (dx*dx + dy*dy).sqrt().max(self.dthr);
+            let s = if dx == 0.0 && dy == 0.0 { 0.0 } else { (gm*om - kc*oc)/(d*d*d) };
+            fv.0 += s*dx;
+            fv.1 += s*dy;
+        }
+
+        fv
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "sse2")]
+    unsafe fn force_on_sse2(&self, p: &Particles, i: usize) -> (f32, f32) {
+        use std::arch::x86_64::*;
+
+        let n = p.len();
+        let (x, y) = (_mm_set1_ps(p.px[i]), _mm_set1_ps(p.py[i]));
+        let (gm, kc) = (_mm_set1_ps(self.g*p.m[i]), _mm_set1_ps(self.k*p.c[i]));
+        let (dthr, zero) = (_mm_set1_ps(self.dthr), _mm_setzero_ps());
+        let (mut fx, mut fy) = (zero, zero);
+
+        let mut j = 0;
+        while j + 4 <= n {
+            let dx = _mm_sub_ps(_mm_loadu_ps(p.px.as_ptr().add(j)), x);
+            let dy = _mm_sub_ps(_mm_loadu_ps(p.py.as_ptr().add(j)), y);
+            let d  = _mm_max_ps(_mm_sqrt_ps(_mm_add_ps(_mm_mul_ps(dx, dx), _mm_mul_ps(dy, dy))), dthr);
+            let d3 = _mm_mul_ps(_mm_mul_ps(d, d), d);
+
+            let q = _mm_sub_ps(_mm_mul_ps(gm, _mm_loadu_ps(p.m.as_ptr().add(j))),
+                               _mm_mul_ps(kc, _mm_loadu_ps(p.c.as_ptr().add(j))));
+            // zero for coincident particles (the particle itself among them)
+            let same = _mm_and_ps(_mm_cmpeq_ps(dx, zero), _mm_cmpeq_ps(dy, zero));
+            let s = _mm_andnot_ps(same, _mm_div_ps(q, d3));
+
+            fx = _mm_add_ps(fx, _mm_mul_ps(s, dx));
+            fy = _mm_add_ps(fy, _mm_mul_ps(s, dy));
+            j += 4;
+        }
+
+        let (mut lx, mut ly) = ([0.0f32; 4], [0.0f32; 4]);
+        _mm_storeu_ps(lx.as_mut_ptr(), fx);
+        _mm_storeu_ps(ly.as_mut_ptr(), fy);
+        let tail = self.force_on_scalar(p, i, j);
+
+        ((lx[0] + lx[1]) + (lx[2] + lx[3]) + tail.0, (ly[0] + ly[1]) + (ly[2] + ly[3]) + tail.1)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use node::init_nodes_vec;
+    use world::{node_force, seeded_rng, Constants};
+    use boundary::Boundary;
+    use force::{LennardJones, Gravity};
+
+    #[test]
+    fn roundtrip_keeps_node_layout() {
+        let mut v = Vec::new();
+        init_nodes_vec(&mut v, 10, &mut seeded_rng(1));
+        v[3].fx = 2.5;
+        let p = Particles::from_nodes(&v);
+
+        assert_eq!(p.len(), 10);
+        assert_eq!(p.node(3), v[3]);
+        assert_eq!(p.to_nodes(), v);
+    }
+
+    #[test]
+    fn set_and_retain_keep_fields_together() {
+        let mut v = Vec::new();
+        init_nodes_vec(&mut v, 6, &mut seeded_rng(4));
+        let mut p = Particles::from_nodes(&v);
+
+        p.set(1, v[5]);
+        p.retain(&[true, true, false, true, false, false]);
+        assert_eq!(p.to_nodes(), vec![v[0], v[5], v[3]]);
+    }
+
+    #[test]
+    fn kernel_matches_generic_force() {
+        let mut v = Vec::new();
+        init_nodes_vec(&mut v, 203, &mut seeded_rng(2)); // not a multiple of 4
+        v.push(v[0]); // coincident pair
+        let laws = Constants::default().laws();
+        let kernel = Kernel::from_laws(&laws).unwrap();
+        let p = Particles::from_nodes(&v);
+
+        for i in (0..v.len()).step_by(13) {
+            let (e, a, s) = (node_force(&v[i], &v, Boundary::Open, &laws[..]),
+                             kernel.force_on(&p, i), kernel.force_on_scalar(&p, i, 0));
+            for &(x, y) in &[a, s] {
+                assert!((e.0 - x).abs() <= 1e-3*e.0.abs().max(1.0), "{} {:?} {:?}", i, e, x);
+                assert!((e.1 - y).abs() <= 1e-3*e.1.abs().max(1.0), "{} {:?} {:?}", i, e, y);
+            }
+        }
+    }
+
+    #[test]
+    fn kernel_only_for_gravity_and_coulomb() {
+        let k = Constants { gravity: 2.0, coulomb: 3.0, dthr: 1.0 };
+        assert_eq!(Kernel::from_laws(&k.laws()), Some(Kernel { g: 2.0, k: 3.0, dthr: 1.0 }));
+        assert_eq!(Kernel::from_laws(&[]), None);
+
+        let mut mixed = k.laws();
+        mixed.push(Law::Gravity(Gravity { g: 1.0, dthr: 2.0 }));
+        assert_eq!(Kernel::from_laws(&mixed), None);
+        assert_eq!(Kernel::from_laws(&[Law::LennardJones(LennardJones { epsilon: 1.0, sigma: 1.0, cutoff: 2.0 })]),
+                   None);
+    }
+}
@@ -0,0 +1,59 @@
+//! Pixels of round particle sprites, for renderers to upload as textures.
+
+/// Sprite sizes built by renderers, smallest first; a node is drawn with
+/// the smallest one at least as large as its on-screen diameter.
+pub const SPRITE_SIZES: [u32; 6] = [2, 4, 8, 16, 32, 64];
+
+
+/// White disc of `size`×`size` pixels as RGBA bytes, row by row.
+/// Coverage of the edge pixel gives the anti-aliased alpha, and the
+/// brightness falls off smoothly towards the rim so it reads as a body.
+pub fn disc_rgba(size: u32) -> Vec<u8> {
+    let r = 0.5*size as f32;
+    let mut px = Vec::with_capacity((size*size*4) as usize);
+
+    for y in 0..size {
+        for x in 0..size {
+            let (dx, dy) = (x as f32 + 0.5 - r, y as f32 + 0.5 - r);
+            let d = (dx*dx + dy*dy).sqrt();
+
+            let alpha = (r - d + 0.5).clamp(0.0, 1.0);
+            let shade = 1.0 - 0.45*(d/r).min(1.0).powi(2);
+            let v = (255.0*shade).round() as u8;
+            px.extend_from_slice(&[v, v, v, (255.0*alpha).round() as u8]);
+        }
+    }
+    px
+}
+
+/// Smallest sprite size from `SPRITE_SIZES` covering `diameter` pixels.
+pub fn sprite_size(diameter: f32) -> u32 {
+    SPRITE_SIZES.iter().cloned().find(|&s| s as f32 >= diameter).unwrap_or(SPRITE_SIZES[SPRITE_SIZES.len() - 1])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disc_is_opaque_inside_and_clear_outside() {
+        let s = 16;
+        let px = disc_rgba(s);
+        let at = |x: u32, y: u32| &px[((y*s + x)*4) as usize..((y*s + x)*4 + 4) as usize];
+
+        assert_eq!(px.len(), (s*s*4) as usize);
+        assert_eq!(at(8, 8)[3], 255);
+        assert_eq!(at(0, 0)[3], 0);
+        assert!(at(8, 8)[0] > at(8, 1)[0]);          // darker towards the rim
+        assert!(at(0, 8)[3] > 0 && at(0, 8)[3] < 255); // partially covered edge
+        assert_eq!(at(3, 5), at(12, 10));            // symmetric
+    }
+
+    #[test]
+    fn sprite_size_covers_diameter() {
+        assert_eq!(sprite_size(0.5), 2);
+        assert_eq!(sprite_size(5.0), 8);
+        assert_eq!(sprite_size(1000.0), 64);
+    }
+}
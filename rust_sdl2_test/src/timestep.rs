@@ -0,0 +1,112 @@
+//! Adaptive global timestep, for close encounters.
+//!
+//! The step is the smaller of two criteria over all nodes: the acceleration
+//! criterion `eta*sqrt(length/|a|)` (time to cover `length` from rest) and the
+//! Courant criterion `courant*length/|v|` (fraction of `length` per step), kept
+//! within `[min_dt, max_dt]`. `length` is the scale that must be resolved,
+//! typically the force clamp distance.
+//!
+//! ```toml
+//! [timestep]
+//! eta     = 0.2
+//! courant = 0.2
+//! length  = 4.0
+//! min_dt  = 0.0001
+//! max_dt  = 0.01
+//! ```
+
+use soa::Particles;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdaptiveDt {
+    pub eta:     f32, // acceleration criterion factor
+    pub courant: f32, // Courant number
+    pub length:  f32, // resolved length scale
+    pub min_dt:  f32,
+    pub max_dt:  f32,
+}
+
+impl Default for AdaptiveDt {
+    fn default() -> AdaptiveDt {
+        AdaptiveDt { eta: 0.2, courant: 0.2, length: 4.0, min_dt: 1e-4, max_dt: 0.01 }
+    }
+}
+
+impl AdaptiveDt {
+    /// Checks that all criteria are positive and the bounds are in order.
+    pub fn check(&self) -> Result<(), String> {
+        let all = [self.eta, self.courant, self.length, self.min_dt, self.max_dt];
+        if !all.iter().all(|x| x.is_finite() && *x > 0.0) {
+            return Err(format!("timestep parameters must be positive: {:?}", self));
+        }
+        if self.min_dt > self.max_dt {
+            return Err(format!("timestep min_dt {} is above max_dt {}", self.min_dt, self.max_dt));
+        }
+        Ok(())
+    }
+
+    /// Step for the current state of `p` (accelerations from the last step).
+    pub fn dt(&self, p: &Particles) -> f32 {
+        let mut a2 = 0.0f32;
+        for (ax, ay) in p.ax.iter().zip(&p.ay) { a2 = a2.max(ax*ax + ay*ay); }
+        let mut v2 = 0.0f32;
+        for (vx, vy) in p.vx.iter().zip(&p.vy) { v2 = v2.max(vx*vx + vy*vy); }
+
+        let mut dt = self.max_dt;
+        if a2 > 0.0 { dt = dt.min(self.eta*(self.length/a2.sqrt()).sqrt()); }
+        if v2 > 0.0 { dt = dt.min(self.courant*self.length/v2.sqrt()); }
+        dt.max(self.min_dt).min(self.max_dt)
+    }
+}
+
+
+/// Step of at most `dt` towards the end of a span with `left` time remaining;
+/// the last two steps are split evenly, so no sliver is left over.
+pub fn fit_step(dt: f32, left: f32) -> f32 {
+    if left <= dt { left } else if left < 2.0*dt { 0.5*left } else { dt }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use node::Node;
+
+    #[test]
+    fn step_follows_fastest_node_within_bounds() {
+        let a = AdaptiveDt::default();
+        let mut v = Particles::from_nodes(&[Node::new(0.0, 0.0, 0.0, 0.0, 1.0, 0.0)]);
+        assert_eq!(a.dt(&v), a.max_dt); // at rest
+
+        v.push(Node::new(0.0, 0.0, 400.0, 0.0, 1.0, 0.0));
+        assert!((a.dt(&v) - 0.2*4.0/400.0).abs() < 1e-7);
+
+        v.ax[0] = 1e6;
+        assert!((a.dt(&v) - 0.2*(4.0f32/1e6).sqrt()).abs() < 1e-7);
+
+        v.ax[0] = 1e12;
+        assert_eq!(a.dt(&v), a.min_dt);
+    }
+
+    #[test]
+    fn bad_bounds_are_rejected() {
+        assert!(AdaptiveDt::default().check().is_ok());
+        assert!(AdaptiveDt { min_dt: 0.05, ..AdaptiveDt::default() }.check().is_err());
+        assert!(AdaptiveDt { eta: 0.0, ..AdaptiveDt::default() }.check().is_err());
+        assert!(AdaptiveDt { max_dt: f32::NAN, ..AdaptiveDt::default() }.check().is_err());
+    }
+
+    #[test]
+    fn fitted_steps_end_exactly_on_span() {
+        let (mut left, mut n) = (0.025f32, 0);
+        while left > 0.0 {
+            let h = fit_step(0.01, left);
+            assert!(h > 0.004 && h <= 0.01);
+            left -= h;
+            n += 1;
+        }
+        assert_eq!((left, n), (0.0, 3));
+    }
+}
@@ -0,0 +1,103 @@
+//! Recent positions of every node, for drawing trails behind them.
+//!
+//! Trails follow nodes by their stable `Node::id`, so emitted nodes and merges
+//! shifting indices do not mix them up; the trail of a node that was merged
+//! away is dropped.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use node::Node;
+use boundary::Boundary;
+
+
+pub struct Trails {
+    len:    usize,
+    frame:  u64, // number of `record` calls, tells trails of removed nodes
+    points: HashMap<u64, (u64, VecDeque<(f32, f32)>)>, // per node id: last frame recorded, ring buffer oldest first
+}
+
+impl Trails {
+    /// Trails of at most `len` positions each.
+    pub fn new(len: usize) -> Trails {
+        Trails { len, frame: 0, points: HashMap::new() }
+    }
+
+    pub fn max_len(&self) -> usize { self.len }
+
+    pub fn set_max_len(&mut self, len: usize) {
+        self.len = len;
+        for &mut (_, ref mut p) in self.points.values_mut() {
+            while p.len() > len { p.pop_front(); }
+        }
+    }
+
+    pub fn clear(&mut self) { self.points.clear(); }
+
+    /// Appends the current position of every node. Jumps across a periodic
+    /// boundary restart the trail, so it is not drawn across the box.
+    pub fn record(&mut self, nodes: &[Node], boundary: Boundary) {
+        self.frame += 1;
+        let (cap, frame) = (self.len, self.frame);
+
+        let half = boundary.size().map(|(w, h)| (0.5*w, 0.5*h));
+        for n in nodes {
+            let t = self.points.entry(n.id).or_insert_with(|| (frame, VecDeque::with_capacity(cap)));
+            t.0 = frame;
+            let p = &mut t.1;
+
+            if let (Some(&(x, y)), Some((hw, hh))) = (p.back(), half) {
+                if (n.px - x).abs() > hw || (n.py - y).abs() > hh { p.clear(); }
+            }
+
+            if p.len() == cap { p.pop_front(); }
+            if cap > 0 { p.push_back((n.px, n.py)); }
+        }
+
+        self.points.retain(|_, t| t.0 == frame);
+    }
+
+    /// Trail of the node with `id`, oldest position first.
+    pub fn get(&self, id: u64) -> Option<&VecDeque<(f32, f32)>> {
+        self.points.get(&id).map(|t| &t.1)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_last_positions_and_restarts() {
+        let mut t = Trails::new(3);
+        let mut v = vec![Node { id: 1, ..Node::new(0.0, 0.0, 0.0, 0.0, 1.0, 0.0) }];
+        for i in 0..5 {
+            v[0].px = i as f32;
+            t.record(&v, Boundary::Open);
+        }
+        assert_eq!(t.get(1).unwrap().iter().cloned().collect::<Vec<_>>(), vec![(2.0, 0.0), (3.0, 0.0), (4.0, 0.0)]);
+
+        // wrapping through a periodic boundary
+        v[0].px = -4.0;
+        t.record(&v, Boundary::Periodic { w: 10.0, h: 10.0 });
+        assert_eq!(t.get(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn trails_follow_ids_when_indices_shift() {
+        let mut t = Trails::new(8);
+        let mut v = vec![Node { id: 1, ..Node::new(0.0, 0.0, 0.0, 0.0, 1.0, 0.0) },
+                         Node { id: 2, ..Node::new(5.0, 0.0, 0.0, 0.0, 1.0, 0.0) }];
+        t.record(&v, Boundary::Open);
+
+        // node 1 merged away and node 3 emitted in the same step: same count, shifted indices
+        v.remove(0);
+        v.push(Node { id: 3, ..Node::new(9.0, 9.0, 0.0, 0.0, 1.0, 0.0) });
+        t.record(&v, Boundary::Open);
+
+        assert!(t.get(1).is_none());
+        assert_eq!(t.get(2).unwrap().iter().cloned().collect::<Vec<_>>(), vec![(5.0, 0.0), (5.0, 0.0)]);
+        assert_eq!(t.get(3).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,202 @@
+//! Per-frame particle trajectories written to disk, for offline analysis.
+//!
+//! Two formats, chosen by the file extension (`.csv` or anything else):
+//!
+//! * CSV, one row per particle per frame: `step,time,id,px,py,vx,vy`
+//! * compact binary, little-endian: the header `NBTR` + `u32` version, then
+//!   for every frame `u64` step, `f32` time, `u32` n and n × (`u64` id, `f32` px, py, vx, vy)
+//!
+//! `id` is the stable `Node::id`, so a particle can be followed across frames
+//! even when merges and emission shift its position in the list.
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write, BufWriter};
+use std::path::Path;
+
+use node::Node;
+
+
+const MAGIC: &[u8; 4] = b"NBTR";
+
+/// Version of the binary format written by `TrajectoryWriter`.
+pub const TRAJECTORY_VERSION: u32 = 2;
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Binary,
+}
+
+impl Format {
+    /// `.csv` files get CSV, everything else the binary format.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Format {
+        match path.as_ref().extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Format::Csv,
+            _ => Format::Binary,
+        }
+    }
+}
+
+
+/// One recorded frame, as read back from a binary trajectory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub step:  u64,
+    pub time:  f32,
+    pub nodes: Vec<(u64, f32, f32, f32, f32)>, // (id, px, py, vx, vy)
+}
+
+
+pub struct TrajectoryWriter<W: Write> {
+    out:    W,
+    format: Format,
+    frames: u64,
+}
+
+impl TrajectoryWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<TrajectoryWriter<BufWriter<File>>> {
+        let format = Format::from_path(&path);
+        TrajectoryWriter::new(BufWriter::new(File::create(path)?), format)
+    }
+}
+
+impl<W: Write> TrajectoryWriter<W> {
+    /// Writes the header of `format` to `out`.
+    pub fn new(mut out: W, format: Format) -> io::Result<TrajectoryWriter<W>> {
+        match format {
+            Format::Csv => writeln!(out, "step,time,id,px,py,vx,vy")?,
+            Format::Binary => {
+                out.write_all(MAGIC)?;
+                out.write_all(&TRAJECTORY_VERSION.to_le_bytes())?;
+            },
+        }
+        Ok(TrajectoryWriter { out, format, frames: 0 })
+    }
+
+    pub fn format(&self) -> Format { self.format }
+
+    /// Number of frames written so far.
+    pub fn frames(&self) -> u64 { self.frames }
+
+    pub fn write_frame(&mut self, step: u64, time: f32, nodes: &[Node]) -> io::Result<()> {
+        match self.format {
+            Format::Csv => {
+                for n in nodes {
+                    writeln!(self.out, "{},{},{},{},{},{},{}", step, time, n.id, n.px, n.py, n.vx, n.vy)?;
+                }
+            },
+            Format::Binary => {
+                self.out.write_all(&step.to_le_bytes())?;
+                self.out.write_all(&time.to_le_bytes())?;
+                self.out.write_all(&(nodes.len() as u32).to_le_bytes())?;
+                for n in nodes {
+                    self.out.write_all(&n.id.to_le_bytes())?;
+                    for x in &[n.px, n.py, n.vx, n.vy] {
+                        self.out.write_all(&x.to_le_bytes())?;
+                    }
+                }
+            },
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+
+fn read_array<R: Read, const N: usize>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut b = [0u8; N];
+    r.read_exact(&mut b)?;
+    Ok(b)
+}
+
+/// Reads all frames of a binary trajectory.
+pub fn read_binary<R: Read>(mut r: R) -> io::Result<Vec<Frame>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    if &read_array::<_, 4>(&mut r)? != MAGIC { return Err(invalid("not a trajectory file".to_string())); }
+    let version = u32::from_le_bytes(read_array(&mut r)?);
+    if version != TRAJECTORY_VERSION {
+        return Err(invalid(format!("unsupported trajectory version {} (expected {})", version, TRAJECTORY_VERSION)));
+    }
+
+    let mut frames = Vec::new();
+    loop {
+        // a clean end of file can only come before a frame
+        let mut first = [0u8; 8];
+        match r.read(&mut first[..1])? {
+            0 => return Ok(frames),
+            _ => r.read_exact(&mut first[1..])?,
+        }
+
+        let step = u64::from_le_bytes(first);
+        let time = f32::from_le_bytes(read_array(&mut r)?);
+        let n = u32::from_le_bytes(read_array(&mut r)?) as usize;
+
+        // `n` is not trusted: a corrupt count fails at the end of the file, not in the allocator
+        let mut nodes = Vec::with_capacity(n.min(1 << 16));
+        for _ in 0..n {
+            let id = u64::from_le_bytes(read_array(&mut r)?);
+            let v: [f32; 4] = [f32::from_le_bytes(read_array(&mut r)?), f32::from_le_bytes(read_array(&mut r)?),
+                               f32::from_le_bytes(read_array(&mut r)?), f32::from_le_bytes(read_array(&mut r)?)];
+            nodes.push((id, v[0], v[1], v[2], v[3]));
+        }
+        frames.push(Frame { step, time, nodes });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes() -> Vec<Node> {
+        vec![Node { id: 7, ..Node::new(1.0, -2.0, 0.5, 0.25, 10.0, 5.0) },
+             Node { id: 3, ..Node::new(-3.0, 4.0, -1.0, 2.0, 10.0, -5.0) }]
+    }
+
+    #[test]
+    fn binary_roundtrip() {
+        let mut w = TrajectoryWriter::new(Vec::new(), Format::Binary).unwrap();
+        w.write_frame(0, 0.0, &nodes()).unwrap();
+        w.write_frame(1, 0.01, &nodes()[..1]).unwrap();
+        let bytes = w.finish().unwrap();
+
+        let frames = read_binary(&bytes[..]).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[1].step, frames[1].time), (1, 0.01));
+        assert_eq!(frames[0].nodes, vec![(7, 1.0, -2.0, 0.5, 0.25), (3, -3.0, 4.0, -1.0, 2.0)]);
+        assert_eq!(frames[1].nodes[0].0, 7);
+        assert_eq!(bytes.len(), 8 + (16 + 2*24) + (16 + 24));
+    }
+
+    #[test]
+    fn truncated_frame_is_an_error() {
+        let mut w = TrajectoryWriter::new(Vec::new(), Format::Binary).unwrap();
+        w.write_frame(0, 0.0, &nodes()).unwrap();
+        let mut bytes = w.finish().unwrap();
+        bytes[20..24].copy_from_slice(&u32::MAX.to_le_bytes()); // particle count of the frame
+
+        let err = read_binary(&bytes[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn csv_has_one_row_per_particle() {
+        let mut w = TrajectoryWriter::new(Vec::new(), Format::Csv).unwrap();
+        w.write_frame(3, 0.5, &nodes()).unwrap();
+        let text = String::from_utf8(w.finish().unwrap()).unwrap();
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines, vec!["step,time,id,px,py,vx,vy", "3,0.5,7,1,-2,0.5,0.25", "3,0.5,3,-3,4,-1,2"]);
+        assert_eq!(Format::from_path("run.csv"), Format::Csv);
+        assert_eq!(Format::from_path("run.bin"), Format::Binary);
+    }
+}
@@ -0,0 +1,471 @@
+//! Simulation state: particles, emitters and the stepping logic.
+
+use std::sync::Arc;
+use std::sync::mpsc;
+use rand;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use node::Node;
+use pool::WorkerPool;
+use integrator::{Integrator, apply_forces};
+use collision::Collisions;
+use boundary::Boundary;
+use quadtree::QuadTree;
+use cellgrid::CellGrid;
+use soa::{Particles, Kernel};
+use timestep::AdaptiveDt;
+use magnetic::Magnetic;
+use force::{ForceLaw, Law, Gravity, Coulomb, pair_force};
+
+
+/// Point source of new particles, fired by `World::step`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Emitter {
+    pub x:  f32, pub y:  f32, // position
+    pub vx: f32, pub vy: f32, // velocity of emitted particles
+    pub m:  f32, // mass of emitted particles
+    pub c:  f32, // charge of emitted particles
+    pub jitter: f32, // random offset added to position, in [0, jitter)
+    pub every:  u64, // emits once every `every` steps
+}
+
+impl Emitter {
+    pub fn new(x: f32, y: f32, vx: f32, vy: f32, m: f32, c: f32) -> Emitter {
+        Emitter {x, y, vx, vy, m, c, jitter: 1.0, every: 1}
+    }
+}
+
+
+/// Coefficients of the default pairwise force: `gravity*m1*m2/d^2 - coulomb*c1*c2/d^2`,
+/// with `d` clamped to `dthr` (the force then falls linearly to zero).
+/// Shortcut for a `Gravity` and a `Coulomb` law, see `laws`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Constants {
+    pub gravity: f32,
+    pub coulomb: f32,
+    pub dthr:    f32,
+}
+
+impl Default for Constants {
+    fn default() -> Constants { Constants { gravity: 10.0, coulomb: 10.0, dthr: 4.0 } }
+}
+
+impl Constants {
+    pub fn laws(&self) -> Vec<Law> {
+        vec![Law::Gravity(Gravity { g: self.gravity, dthr: self.dthr }),
+             Law::Coulomb(Coulomb { k: self.coulomb, dthr: self.dthr })]
+    }
+}
+
+
+/// Force computation backend, switchable at runtime.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Solver {
+    Exact,                     // all pairs, O(n^2); SIMD kernel for gravity + Coulomb
+    BarnesHut { theta: f32 },  // quadtree, O(n log n); opening angle theta
+    CellList { cutoff: f32 },  // spatial hash, O(n); pairs beyond cutoff are ignored
+}
+
+impl Solver {
+    /// The cell list only suits `laws` that all vanish within its cutoff;
+    /// long-range forces would be cut off silently.
+    pub fn check(&self, laws: &[Law]) -> Result<(), String> {
+        match (*self, laws.cutoff()) {
+            (Solver::CellList { cutoff }, None) =>
+                Err(format!("cell list (cutoff {}) needs short-range force laws only", cutoff)),
+            (Solver::CellList { cutoff }, Some(c)) if c > cutoff =>
+                Err(format!("force law cutoff {} exceeds the cell list cutoff {}", c, cutoff)),
+            _ => Ok(()),
+        }
+    }
+}
+
+
+/// Random generator for all initialisation and emission; the same seed gives the same run.
+pub fn seeded_rng(seed: u64) -> XorShiftRng {
+    let (lo, hi) = (seed as u32, (seed >> 32) as u32);
+    // mixed with the default xorshift state, never all zero
+    XorShiftRng::from_seed([lo ^ 0x193a_6754, hi ^ 0xa8a7_d469, lo ^ 0x9783_0e05, hi ^ 0x113b_a7bb])
+}
+
+/// Fresh seed for runs started without one.
+pub fn random_seed() -> u64 { rand::random() }
+
+
+// generator for the emissions of step `step`, so a resumed run draws the same jitter
+fn step_rng(seed: u64, step: u64) -> XorShiftRng {
+    // splitmix64 of the pair, neighbouring steps get unrelated generators
+    let mut z = seed ^ step.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    seeded_rng(z ^ (z >> 31))
+}
+
+
+/// Headless 2D particle world; front-ends only read `nodes()` to draw it.
+pub struct World {
+    particles: Arc<Particles>,  // the nodes, shared read-only with workers while forces are evaluated
+    accels:    bool,            // `ax, ay` belong to the current positions, Verlet may reuse them
+    next_id:   u64,             // id of the next node added
+    emitters:  Vec<Emitter>,
+    max_nodes: usize, // emitters stop when reached
+    time:      f32,   // simulated time
+    steps:     u64,   // step counter
+    seed:      u64,
+    rng:       XorShiftRng, // placing new nodes; emitters draw from `step_rng`
+    solver:    Solver,
+    integrator: Integrator,
+    collisions: Collisions,
+    boundary:  Boundary,
+    adaptive:  Option<AdaptiveDt>, // none means the front-end's fixed dt
+    magnetic:  Option<Magnetic>,   // out-of-plane field, Lorentz force on charged nodes
+    laws:      Arc<Vec<Law>>,   // summed pairwise force laws, shared with workers
+    pool:      WorkerPool,
+    snapshot:  Arc<Vec<Node>>,   // `Node` layout copy for the generic solvers, reused
+    forces:    Vec<(f32, f32)>,  // per-node forces, reused
+    grid:      Arc<CellGrid>,    // cell list of the snapshot, rebuilt in place
+}
+
+impl Default for World {
+    fn default() -> World { World::new() }
+}
+
+impl World {
+    pub fn new() -> World {
+        let seed = random_seed();
+        World {
+            particles: Arc::new(Particles::default()),
+            accels:    false,
+            next_id:   1,
+            emitters:  Vec::new(),
+            max_nodes: usize::MAX,
+            time:      0.0,
+            steps:     0,
+            seed,
+            rng:       seeded_rng(seed),
+            solver:    Solver::Exact,
+            integrator: Integrator::default(),
+            collisions: Collisions::default(),
+            boundary:  Boundary::default(),
+            adaptive:  None,
+            magnetic:  None,
+            laws:      Arc::new(Constants::default().laws()),
+            pool:      WorkerPool::with_cpu_count(),
+            snapshot:  Arc::new(Vec::new()),
+            forces:    Vec::new(),
+            grid:      Arc::new(CellGrid::default()),
+        }
+    }
+
+    /// Restarts the world's random generators from `seed`. Emitter jitter depends
+    /// only on the seed and the step, so it carries over to resumed runs.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng  = seeded_rng(seed);
+    }
+
+    pub fn seed(&self) -> u64 { self.seed }
+
+    /// The world's random generator, for placing new nodes.
+    pub fn rng_mut(&mut self) -> &mut XorShiftRng { &mut self.rng }
+
+    pub fn with_max_nodes(max_nodes: usize) -> World {
+        World { max_nodes, ..World::new() }
+    }
+
+    /// Copy of all nodes in the `Node` layout, e.g. for rendering.
+    pub fn nodes(&self) -> Vec<Node> { self.particles.to_nodes() }
+
+    pub fn node(&self, i: usize) -> Node { self.particles.node(i) }
+
+    pub fn particles(&self) -> &Particles { &self.particles }
+
+    pub fn particles_mut(&mut self) -> &mut Particles {
+        self.accels = false;
+        Arc::make_mut(&mut self.particles)
+    }
+
+    pub fn len(&self) -> usize { self.particles.len() }
+
+    pub fn is_empty(&self) -> bool { self.particles.is_empty() }
+
+    pub fn time(&self) -> f32 { self.time }
+
+    pub fn steps(&self) -> u64 { self.steps }
+
+    /// Sets simulated time and step counter, e.g. when resuming a run.
+    pub fn set_clock(&mut self, time: f32, steps: u64) {
+        self.time  = time;
+        self.steps = steps;
+    }
+
+    pub fn solver(&self) -> Solver { self.solver }
+
+    pub fn set_solver(&mut self, solver: Solver) {
+        self.solver = solver;
+        self.accels = false;
+    }
+
+    pub fn integrator(&self) -> Integrator { self.integrator }
+
+    pub fn set_integrator(&mut self, integrator: Integrator) { self.integrator = integrator; }
+
+    pub fn collisions(&self) -> Collisions { self.collisions }
+
+    pub fn set_collisions(&mut self, collisions: Collisions) { self.collisions = collisions; }
+
+    pub fn boundary(&self) -> Boundary { self.boundary }
+
+    pub fn set_boundary(&mut self, boundary: Boundary) {
+        self.boundary = boundary;
+        self.accels = false;
+    }
+
+    pub fn magnetic(&self) -> Option<Magnetic> { self.magnetic }
+
+    pub fn set_magnetic(&mut self, magnetic: Option<Magnetic>) {
+        self.magnetic = magnetic;
+        self.accels = false;
+    }
+
+    pub fn adaptive(&self) -> Option<AdaptiveDt> { self.adaptive }
+
+    pub fn set_adaptive(&mut self, adaptive: Option<AdaptiveDt>) { self.adaptive = adaptive; }
+
+    /// Step to take next: `dt`, or the adaptive step for the current state when enabled.
+    pub fn next_dt(&self, dt: f32) -> f32 {
+        self.adaptive.map_or(dt, |a| a.dt(&self.particles))
+    }
+
+    pub fn laws(&self) -> &[Law] { &self.laws }
+
+    pub fn set_laws(&mut self, laws: Vec<Law>) {
+        self.laws = Arc::new(laws);
+        self.accels = false;
+    }
+
+    /// Replaces the force laws with gravity and Coulomb from `constants`.
+    pub fn set_constants(&mut self, constants: Constants) { self.set_laws(constants.laws()); }
+
+    pub fn threads(&self) -> usize { self.pool.size() }
+
+    /// Replaces the worker pool, e.g. to run single-threaded.
+    pub fn set_threads(&mut self, threads: usize) { self.pool = WorkerPool::new(threads); }
+
+    pub fn max_nodes(&self) -> usize { self.max_nodes }
+
+    pub fn set_max_nodes(&mut self, max_nodes: usize) { self.max_nodes = max_nodes; }
+
+    /// Adds `node`, giving it a fresh id unless it has one already (e.g. from a snapshot).
+    pub fn add_node(&mut self, mut node: Node) {
+        if node.id == 0 {
+            node.id = self.next_id;
+        }
+        self.next_id = self.next_id.max(node.id + 1);
+        self.particles_mut().push(node);
+    }
+
+    pub fn add_nodes<I: IntoIterator<Item = Node>>(&mut self, nodes: I) {
+        for n in nodes { self.add_node(n); }
+    }
+
+    /// Id the next added or emitted node gets.
+    pub fn next_id(&self) -> u64 { self.next_id }
+
+    /// Skips ids below `next_id`, e.g. those of nodes merged away before a snapshot,
+    /// so they are not handed out again. Never goes below the ids in use.
+    pub fn set_next_id(&mut self, next_id: u64) { self.next_id = self.next_id.max(next_id); }
+
+    /// Current index of the node with `id`, none once it is merged away.
+    pub fn index_of(&self, id: u64) -> Option<usize> {
+        self.particles.id.iter().position(|&i| i == id)
+    }
+
+    /// Index of the node closest to `pos`, if one is within `radius`.
+    pub fn nearest(&self, pos: (f32, f32), radius: f32) -> Option<usize> {
+        let p = &self.particles;
+        p.px.iter().zip(&p.py).enumerate()
+            .map(|(i, (x, y))| (i, (x - pos.0).powi(2) + (y - pos.1).powi(2)))
+            .filter(|&(_, d2)| d2 <= radius*radius)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(::std::cmp::Ordering::Equal))
+            .map(|(i, _)| i)
+    }
+
+    pub fn add_emitter(&mut self, emitter: Emitter) { self.emitters.push(emitter); }
+
+    pub fn emitters(&self) -> &[Emitter] { &self.emitters }
+
+    pub fn clear_emitters(&mut self) { self.emitters.clear(); }
+
+    /// Fires every emitter due at the current step, up to `max_nodes`.
+    pub fn emit(&mut self) {
+        let particles = Arc::make_mut(&mut self.particles);
+        let mut rng = step_rng(self.seed, self.steps);
+        for e in &self.emitters {
+            if particles.len() >= self.max_nodes { break; }
+            if e.every == 0 || !self.steps.is_multiple_of(e.every) { continue; }
+
+            let (x, y) = (e.x + rng.gen::<f32>()*e.jitter, e.y + rng.gen::<f32>()*e.jitter);
+            particles.push(Node { id: self.next_id, ..Node::new(x, y, e.vx, e.vy, e.m, e.c) });
+            self.next_id += 1;
+            self.accels = false;
+        }
+    }
+
+    /// Emits new particles, advances the simulation by `dt` with the selected integrator,
+    /// then applies the boundary and resolves collisions. Accelerations are kept for
+    /// the next velocity Verlet step only if nothing changed them afterwards: new
+    /// nodes, wall reflections, collisions or new settings make it evaluate the forces first.
+    pub fn step(&mut self, dt: f32) {
+        self.emit();
+
+        let (pool, solver, snapshot, grid) = (&self.pool, self.solver, &mut self.snapshot, &mut self.grid);
+        let (boundary, laws) = (self.boundary, &self.laws);
+
+        // the vectorised kernel knows neither other laws nor periodic images
+        let kernel = match (solver, boundary) {
+            (_, Boundary::Periodic { .. }) => None,
+            (Solver::Exact, _) => Kernel::from_laws(laws),
+            _ => None,
+        };
+
+        let mut eval = |state: &Arc<Particles>, forces: &mut Vec<(f32, f32)>| {
+            if let Some(kernel) = kernel {
+                compute_forces_soa(pool, state, kernel, forces);
+                return;
+            }
+
+            // the generic solvers read a `Node` layout copy, refilled in place
+            // (workers dropped their handles already)
+            {
+                let snap = Arc::make_mut(snapshot);
+                snap.clear();
+                snap.extend((0..state.len()).map(|i| state.node(i)));
+            }
+
+            match solver {
+                Solver::Exact => compute_forces(pool, snapshot, boundary, laws, forces),
+                Solver::BarnesHut { theta } => {
+                    let tree = Arc::new(QuadTree::build(snapshot));
+                    compute_forces_tree(pool, snapshot, &tree, theta, boundary, laws, forces);
+                },
+                Solver::CellList { cutoff } => {
+                    Arc::make_mut(grid).rebuild(snapshot, cutoff, boundary);
+                    compute_forces_grid(pool, snapshot, grid, boundary, laws, forces);
+                },
+            }
+        };
+
+        self.integrator.step(&mut self.particles, &mut self.forces, dt, self.magnetic, self.accels, &mut eval);
+        let particles = Arc::make_mut(&mut self.particles);
+        let reflected = self.boundary.apply(particles);
+        let contacts  = self.collisions.resolve(particles);
+
+        // only Verlet leaves the accelerations of the new positions behind
+        self.accels = self.integrator == Integrator::VelocityVerlet && reflected == 0 && contacts == 0;
+
+        self.time  += dt;
+        self.steps += 1;
+    }
+}
+
+
+/// Total force acting on `n` from all nodes in `others` (exact, all pairs).
+/// With periodic boundary only the nearest image of each node is counted.
+pub fn node_force<L: ForceLaw + ?Sized>(n: &Node, others: &[Node], boundary: Boundary, laws: &L) -> (f32, f32) {
+    let mut fv = (0.0, 0.0);
+
+    for m in others {  // FIXME: 40% core slice iter - main bottleneck
+        if n.px == m.px && n.py == m.py { continue; }
+
+        let (px, py) = boundary.image((n.px, n.py), (m.px, m.py));
+        let f = pair_force(n, &Node { px, py, ..*m }, laws);
+        fv.0 += f.0;  // result force vector - x
+        fv.1 += f.1;  // result force vector - y
+    }
+
+    fv
+}
+
+
+/// Potential energy of a pair under `laws`, consistent with `node_force`.
+pub fn pair_potential<L: ForceLaw + ?Sized>(n: &Node, m: &Node, boundary: Boundary, laws: &L) -> f32 {
+    let pos = boundary.image((n.px, n.py), (m.px, m.py));
+    let dnm = (pos.0 - n.px, pos.1 - n.py);
+    let d   = (dnm.0*dnm.0 + dnm.1*dnm.1).sqrt();
+
+    laws.potential(n, m, d)
+}
+
+
+/// Splits the force loop into one chunk per worker and collects the results into `forces`.
+pub fn compute_forces(pool: &WorkerPool, snapshot: &Arc<Vec<Node>>, boundary: Boundary, laws: &Arc<Vec<Law>>,
+                      forces: &mut Vec<(f32, f32)>) {
+    let (snap, laws) = (snapshot.clone(), laws.clone());
+    par_forces(pool, snapshot.len(), forces, move |i| node_force(&snap[i], &snap, boundary, &laws[..]));
+}
+
+
+/// Like `compute_forces`, but approximates far cells of `tree` (built from `snapshot`).
+pub fn compute_forces_tree(pool: &WorkerPool, snapshot: &Arc<Vec<Node>>, tree: &Arc<QuadTree>,
+                           theta: f32, boundary: Boundary, laws: &Arc<Vec<Law>>, forces: &mut Vec<(f32, f32)>) {
+    let (snap, tree, laws) = (snapshot.clone(), tree.clone(), laws.clone());
+    par_forces(pool, snapshot.len(), forces, move |i| tree.force_on(&snap[i], &snap, theta, boundary, &laws[..]));
+}
+
+
+/// Like `compute_forces`, but only over neighbours within the cutoff of `grid` (built from `snapshot`).
+pub fn compute_forces_grid(pool: &WorkerPool, snapshot: &Arc<Vec<Node>>, grid: &Arc<CellGrid>,
+                           boundary: Boundary, laws: &Arc<Vec<Law>>, forces: &mut Vec<(f32, f32)>) {
+    let (snap, grid, laws) = (snapshot.clone(), grid.clone(), laws.clone());
+    par_forces(pool, snapshot.len(), forces, move |i| grid.force_on(&snap[i], &snap, boundary, &laws[..]));
+}
+
+
+/// Like `compute_forces`, with the vectorised kernel over the structure-of-arrays `particles`.
+pub fn compute_forces_soa(pool: &WorkerPool, particles: &Arc<Particles>, kernel: Kernel,
+                          forces: &mut Vec<(f32, f32)>) {
+    let p = particles.clone();
+    par_forces(pool, particles.len(), forces, move |i| kernel.force_on(&p, i));
+}
+
+
+// runs `force(i)` for i in 0..n on the pool, one chunk per worker
+fn par_forces<F>(pool: &WorkerPool, n: usize, forces: &mut Vec<(f32, f32)>, force: F)
+        where F: Fn(usize) -> (f32, f32) + Send + Sync + 'static {
+    forces.clear();
+    forces.resize(n, (0.0, 0.0));
+    if n == 0 { return; }
+
+    let force = Arc::new(force);
+    let chunk = n.div_ceil(pool.size());
+    let (tx, rx) = mpsc::channel();
+
+    for start in (0..n).step_by(chunk) {
+        let end = (start + chunk).min(n);
+        let f = force.clone();
+        let tx = tx.clone();
+
+        pool.execute(move || {
+            let fv: Vec<(f32, f32)> = (start..end).map(|i| f(i)).collect();
+            drop(f); // releasing the snapshot before the caller wakes up
+            tx.send((start, fv)).unwrap();
+        });
+    }
+    drop(tx);
+    drop(force);
+
+    for (start, fv) in rx {
+        forces[start..start + fv.len()].copy_from_slice(&fv);
+    }
+}
+
+
+// computing forces, velocities, positions
+pub fn update_nodes_vec(v: &mut [Node], pool: &WorkerPool, dt: f32) {
+    let mut forces = Vec::with_capacity(v.len());
+    let kernel = Kernel::from_laws(&Constants::default().laws()).unwrap();
+    compute_forces_soa(pool, &Arc::new(Particles::from_nodes(v)), kernel, &mut forces);
+    apply_forces(v, &forces, dt);
+}